@@ -0,0 +1,159 @@
+#!/usr/bin/env python3
+"""Generates src/unicode/tables.rs, the Unicode tables used by `\\p{..}`, the Unicode-aware
+`\\d`, `\\w` and `\\s`, and case-insensitive matching.
+
+The data comes from the third party `regex` module (`pip install regex`), which follows newer
+Unicode versions than the standard library's `unicodedata`. Run it from the root of the crate:
+
+    python3 scripts/generate_unicode_tables.py > src/unicode/tables.rs
+"""
+
+import sys
+
+import regex
+import regex._regex as regex_impl
+import regex._regex_core as regex_core
+
+# Two letter general categories, and the ones grouping them
+CATEGORIES = ["Cc", "Cf", "Cn", "Co", "Cs", "Ll", "Lm", "Lo", "Lt", "Lu", "Mc", "Me", "Mn", "Nd", "Nl",
+              "No", "Pc", "Pd", "Pe", "Pf", "Pi", "Po", "Ps", "Sc", "Sk", "Sm", "So", "Zl", "Zp",
+              "Zs"]
+GROUPS = {
+    "C": ["Cc", "Cf", "Cn", "Co", "Cs"],
+    "L": ["Ll", "Lm", "Lo", "Lt", "Lu"],
+    "LC": ["Ll", "Lt", "Lu"],
+    "M": ["Mc", "Me", "Mn"],
+    "N": ["Nd", "Nl", "No"],
+    "P": ["Pc", "Pd", "Pe", "Pf", "Pi", "Po", "Ps"],
+    "S": ["Sc", "Sk", "Sm", "So"],
+    "Z": ["Zl", "Zp", "Zs"],
+}
+
+# Every character but the surrogates, which can't be a `char`
+CHARS = "".join(chr(c) for c in range(0x110000) if not 0xD800 <= c <= 0xDFFF)
+
+
+def ranges(*props):
+    """Ranges of the characters having any of the properties."""
+    pattern = "[%s]+" % "".join(r"\p{%s}" % prop for prop in props)
+    return [(ord(m.group()[0]), ord(m.group()[-1])) for m in regex.finditer(pattern, CHARS)]
+
+
+def normalize(name):
+    # loose matching of property names: case, spaces, underscores and hyphens are ignored
+    return "".join(c for c in name.lower() if c not in " _-")
+
+
+def char(c):
+    return "'\\u{%x}'" % c
+
+
+def table(name, ranges):
+    lines = ["const %s: Ranges = &[" % name]
+    line = "   "
+    for start, end in ranges:
+        item = " (%s, %s)," % (char(start), char(end))
+        if len(line) + len(item) > 100:
+            lines.append(line)
+            line = "   "
+        line += item
+    if ranges:
+        lines.append(line)
+    lines.append("];")
+    return "\n".join(lines)
+
+
+def case_folding():
+    """Characters with other cases under simple case folding, with these other cases."""
+    folded = {}
+    for c in CHARS:
+        # the regex module keeps I as is to handle the Turkic dotless ı, it's folded to i otherwise
+        fold = "i" if c == "I" else regex_impl.fold_case(regex.IGNORECASE | regex.UNICODE, c)
+        folded.setdefault(fold, []).append(ord(c))
+    folding = []
+    for cases in folded.values():
+        for c in cases:
+            if len(cases) > 1:
+                folding.append((c, [other for other in cases if other != c]))
+    folding.sort()
+    lines = ["pub(crate) static CASE_FOLDING: &[(char, &[char])] = &["]
+    line = "   "
+    for c, others in folding:
+        item = " (%s, &[%s])," % (char(c), ", ".join(char(other) for other in others))
+        if len(line) + len(item) > 100:
+            lines.append(line)
+            line = "   "
+        line += item
+    lines.append(line)
+    lines.append("];")
+    return "\n".join(lines)
+
+
+def main():
+    out = []
+    out.append("// DO NOT EDIT: generated by scripts/generate_unicode_tables.py from the Unicode data of")
+    out.append("// the Python regex module %s" % regex.__version__)
+    out.append("")
+    out.append("pub(crate) type Ranges = &'static [(char, char)];")
+    out.append("")
+
+    # names of every general category, the aliases coming from the regex module
+    gc_ids = regex_core.PROPERTIES["GENERALCATEGORY"][1]
+    names = {}
+    for category in CATEGORIES + list(GROUPS):
+        for alias, id in gc_ids.items():
+            if id == gc_ids[category.upper()] and "&" not in alias:
+                names[normalize(alias)] = category
+    out.append("/// General categories by their normalized names, with the tables of their subcategories.")
+    out.append("pub(crate) static GENERAL_CATEGORY: &[(&str, &[Ranges])] = &[")
+    for name, category in sorted(names.items()):
+        subcategories = GROUPS.get(category, [category])
+        out.append('    ("%s", &[%s]),' % (name, ", ".join(c.upper() for c in subcategories)))
+    out.append("];")
+    out.append("")
+
+    # a script's aliases are its full name and its four letter code, the full name is the longest
+    # one unless it has four letters as well, like Thai
+    sc_ids = regex_core.PROPERTIES["SCRIPT"][1]
+    scripts = {}
+    for alias, id in sc_ids.items():
+        scripts.setdefault(id, []).append(alias)
+    script_tables = {}
+    names = {}
+    for aliases in scripts.values():
+        full_name = max(aliases, key=lambda alias: (len(alias) != 4, len(alias)))
+        script_tables[full_name] = ranges("Script=%s" % full_name)
+        for alias in aliases:
+            names[normalize(alias)] = full_name
+    out.append("/// Scripts by their normalized names.")
+    out.append("pub(crate) static SCRIPT: &[(&str, Ranges)] = &[")
+    for name, full_name in sorted(names.items()):
+        out.append('    ("%s", %s),' % (name, full_name))
+    out.append("];")
+    out.append("")
+
+    out.append("/// Characters matched by `\\d` in Unicode mode.")
+    out.append("pub(crate) const PERL_DIGIT: Ranges = ND;")
+    out.append("")
+    out.append("/// Characters matched by `\\s` in Unicode mode.")
+    out.append("pub(crate) const PERL_SPACE: Ranges = WHITE_SPACE;")
+    out.append("")
+    out.append("/// Characters matched by `\\w` in Unicode mode, as defined by UTS #18.")
+    out.append("pub(crate) const PERL_WORD: Ranges = WORD;")
+    out.append("")
+    out.append("/// Characters with other cases, sorted, along with these other cases: the characters")
+    out.append("/// which are the same once simply case folded.")
+    out.append(case_folding())
+    out.append("")
+
+    for category in CATEGORIES:
+        out.append(table(category.upper(), ranges("gc=%s" % category)))
+    for full_name in sorted(script_tables):
+        out.append(table(full_name, script_tables[full_name]))
+    out.append(table("WHITE_SPACE", ranges("White_Space")))
+    out.append(table("WORD", ranges("Alphabetic", "M", "Nd", "Pc", "Join_Control")))
+    sys.stdout.write("\n".join(out) + "\n")
+
+
+if __name__ == "__main__":
+    main()
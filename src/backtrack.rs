@@ -0,0 +1,788 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::iter;
+use std::ops::Range;
+use std::rc::Rc;
+use std::slice;
+
+use crate::unicode::other_cases;
+use crate::{char_at, MatchError, Quantifier, RType, RE};
+
+// A search gives up after this many steps, plus a few more for every node of the pattern and byte
+// of the haystack, rather than running for hours on a pattern like `(a*)*\1b`
+const MIN_STEPS: usize = 1 << 20;
+const STEPS_PER_NODE_AND_BYTE: usize = 8;
+
+/// Span of every capture group of a match, `slots[0]` being the whole match and `slots[n]` group
+/// `n`. Groups that didn't take part in the match are `None`.
+pub(crate) type Slots = Vec<Option<Range<usize>>>;
+
+// What's left to match once the current RType matched, as a list of tasks shared by the choice
+// points continuing from the same place. `None` is the end of the pattern.
+type Cont<'r> = Option<Rc<Link<'r>>>;
+
+struct Link<'r> {
+    task: Task<'r>,
+    next: Cont<'r>,
+    last_start: usize, // latest start of a repetition in the tasks, 0 without any
+    atomic: bool,      // whether the tasks end an atomic group
+    id: Cell<Option<u32>>, // number given by `Memo::id` past `last_start`, once it's needed
+}
+
+#[derive(Clone, Copy)]
+enum Task<'r> {
+    Seq(&'r [RType]), // match the RTypes one after the other
+    Repeat(&'r RType, &'r Quantifier, u32, usize), // iterations matched, start of the last one
+    CaptureEnd(usize, usize), // set the span of a group which started there
+    AtomicEnd(usize), // drop the choice points above that stack length
+}
+
+// Backtracking state, the stack is unwound until a choice point when the pattern fails to match
+enum Frame<'r> {
+    Choice(usize, Cont<'r>), // another way to match the rest from a position
+    RestoreSlot(usize, Option<Range<usize>>), // undo the capture of a group
+}
+
+// Loops already run from a position without leading to a match, so that they aren't run again
+// from there: the ways to split a haystack between nested quantifiers like in `(a|a)*b` aren't
+// all tried then. Without back-references the captures don't change whether the rest of the
+// pattern matches, so continuations are told apart by the tasks left only.
+struct Memo {
+    ids: HashMap<(TaskKey, u32), u32>, // continuations numbered from 1, 0 being the end
+    visited: Vec<Visited>,             // for every nested run, the outermost one first
+    len: usize,                        // number of positions in the haystack
+}
+
+// What a task does from a position. A repetition only depends on where its last iteration
+// started through whether that's the position, and past its minimum the number of iterations
+// only matters up to the maximum.
+#[derive(PartialEq, Eq, Hash)]
+enum TaskKey {
+    Seq(*const RType, usize),
+    Repeat(*const RType, u32, bool),
+    CaptureEnd(usize),
+    AtomicEnd,
+}
+
+// Set of (continuation, position) pairs, a bitset over the positions for every continuation.
+// The words set are remembered so that a lookaround run often on a long haystack is cheap to
+// clear.
+#[derive(Default)]
+struct Visited {
+    bits: Vec<Vec<u64>>,
+    touched: Vec<(u32, usize)>,
+}
+
+impl Memo {
+    fn new(len: usize) -> Self {
+        Memo {
+            ids: HashMap::new(),
+            visited: vec![Visited::default()],
+            len,
+        }
+    }
+
+    // Number of the continuation starting with `link` when it's run from `at`
+    fn id(&mut self, link: &Link, at: usize) -> u32 {
+        let cacheable = at > link.last_start;
+        if let Some(id) = link.id.get().filter(|_| cacheable) {
+            return id;
+        }
+        let next = link.next.as_ref().map_or(0, |next| self.id(next, at));
+        let key = match link.task {
+            Task::Seq(rtypes) => TaskKey::Seq(rtypes.as_ptr(), rtypes.len()),
+            Task::Repeat(rtype, quantifier, count, start) => {
+                let count = match quantifier.max {
+                    Some(_) => count,
+                    None => count.min(quantifier.min + 1),
+                };
+                TaskKey::Repeat(
+                    rtype as *const RType,
+                    count,
+                    count > quantifier.min && start == at,
+                )
+            }
+            Task::CaptureEnd(group, _) => TaskKey::CaptureEnd(group),
+            Task::AtomicEnd(_) => TaskKey::AtomicEnd,
+        };
+        let count = self.ids.len() as u32;
+        let id = *self.ids.entry((key, next)).or_insert(count + 1);
+        if cacheable {
+            link.id.set(Some(id));
+        }
+        id
+    }
+}
+
+impl Visited {
+    // Adds `id` at `at` to the set, returns whether it wasn't there already
+    fn insert(&mut self, id: u32, at: usize, len: usize) -> bool {
+        let index = id as usize;
+        if self.bits.len() <= index {
+            self.bits.resize_with(index + 1, Vec::new);
+        }
+        let bits = &mut self.bits[index];
+        if bits.is_empty() {
+            *bits = vec![0; len / 64 + 1];
+        }
+        let (word, bit) = (at / 64, 1 << (at % 64));
+        if bits[word] & bit != 0 {
+            return false;
+        }
+        if bits[word] == 0 {
+            self.touched.push((id, word));
+        }
+        bits[word] |= bit;
+        true
+    }
+
+    fn clear(&mut self) {
+        for (id, word) in self.touched.drain(..) {
+            self.bits[id as usize][word] = 0;
+        }
+    }
+}
+
+/// Finds the leftmost match of `re` in `haystack` starting at byte offset `start` or after it,
+/// only at `start` if `anchored`. Gives up with an error once the search took too many steps.
+pub(crate) fn search(
+    re: &RE,
+    haystack: &str,
+    start: usize,
+    anchored: bool,
+) -> Result<Option<Slots>, MatchError> {
+    if anchored {
+        return match_at(re, haystack, start);
+    }
+    let mut backtracker = Backtracker::new(re, haystack);
+    // the pattern is only tried where its first character can match, if it has to match one
+    let mut firsts = vec![];
+    let firsts = (first_chars(&re.rtype, &mut firsts) == Some(false)).then_some(firsts);
+    for (at, c) in haystack[start..].char_indices() {
+        let at = start + at;
+        if firsts
+            .as_ref()
+            .is_some_and(|firsts| !firsts.iter().any(|first| first.matches_char(c)))
+        {
+            continue;
+        }
+        if let Some(slots) = backtracker.match_at(at)? {
+            return Ok(Some(slots));
+        }
+    }
+    match firsts {
+        Some(_) => Ok(None),
+        None => backtracker.match_at(haystack.len()),
+    }
+}
+
+/// Matches `re` starting exactly at byte offset `at` of `haystack`, trying every way the pattern
+/// can match until one of them succeeds.
+pub(crate) fn match_at(re: &RE, haystack: &str, at: usize) -> Result<Option<Slots>, MatchError> {
+    Backtracker::new(re, haystack).match_at(at)
+}
+
+// Adds the RTypes matching a single character one of which has to match the first character
+// matched by `rtypes` to `firsts`. Returns whether `rtypes` can match the empty string, `None`
+// when what it starts with isn't known, like with a back-reference.
+fn first_chars<'r>(rtypes: &'r [RType], firsts: &mut Vec<&'r RType>) -> Option<bool> {
+    for rtype in rtypes {
+        let empty = match rtype {
+            RType::Repeat(rtype, quantifier) => {
+                first_chars(slice::from_ref(rtype), firsts)? || quantifier.min == 0
+            }
+            RType::AltOr(branches) => {
+                let mut empty = false;
+                for branch in branches {
+                    empty |= first_chars(branch, firsts)?;
+                }
+                empty
+            }
+            RType::Capture(_, rtypes) | RType::Group(rtypes) | RType::Atomic(rtypes) => {
+                first_chars(rtypes, firsts)?
+            }
+            RType::BackRefs(_) | RType::BackRefsNoCase(_) => return None,
+            // zero-width assertions don't match any character
+            RType::Anchor(_)
+            | RType::LookAhead(_)
+            | RType::NegLookAhead(_)
+            | RType::LookBehind(..)
+            | RType::NegLookBehind(..) => true,
+            _ => {
+                firsts.push(rtype);
+                false
+            }
+        };
+        if !empty {
+            return Some(false);
+        }
+    }
+    Some(true)
+}
+
+// Adds a task in front of the continuation `next`
+fn then<'r>(task: Task<'r>, next: Cont<'r>) -> Cont<'r> {
+    let start = match task {
+        Task::Repeat(.., start) => start,
+        _ => 0,
+    };
+    let (last_start, atomic) = next
+        .as_ref()
+        .map_or((0, false), |next| (next.last_start, next.atomic));
+    Some(Rc::new(Link {
+        atomic: atomic || matches!(task, Task::AtomicEnd(_)),
+        task,
+        next,
+        last_start: last_start.max(start),
+        id: Cell::new(None),
+    }))
+}
+
+// Number of nodes of `rtypes` once the bounded repetitions are unrolled like in the NFA, and
+// whether there's a back-reference among them
+fn size(rtypes: &[RType], backrefs: &mut bool) -> usize {
+    let mut total = 0usize;
+    for rtype in rtypes {
+        let nested = match rtype {
+            RType::Repeat(rtype, quantifier) => {
+                let times = quantifier.max.unwrap_or(quantifier.min + 1).max(1);
+                size(slice::from_ref(rtype), backrefs).saturating_mul(times as usize)
+            }
+            RType::AltOr(branches) => branches.iter().fold(0, |sum: usize, branch| {
+                sum.saturating_add(size(branch, backrefs))
+            }),
+            RType::Capture(_, rtypes)
+            | RType::Group(rtypes)
+            | RType::Atomic(rtypes)
+            | RType::LookAhead(rtypes)
+            | RType::NegLookAhead(rtypes)
+            | RType::LookBehind(_, _, rtypes)
+            | RType::NegLookBehind(_, _, rtypes) => size(rtypes, backrefs),
+            RType::BackRefs(_) | RType::BackRefsNoCase(_) => {
+                *backrefs = true;
+                0
+            }
+            _ => 0,
+        };
+        total = total.saturating_add(nested).saturating_add(1);
+    }
+    total
+}
+
+// The choice points are kept on the heap rather than on the call stack, so that a pattern like
+// `.*` trying every length of a long haystack doesn't overflow it
+pub(crate) struct Backtracker<'r, 'h> {
+    pattern: Cont<'r>, // the whole pattern, from where every match starts
+    haystack: &'h str,
+    slots: Slots,
+    stack: Vec<Frame<'r>>,
+    steps: usize,       // left before the search gives up
+    memo: Option<Memo>, // `None` when the pattern has back-references
+    depth: usize,       // number of lookarounds the current run is nested in
+}
+
+impl<'r, 'h> Backtracker<'r, 'h> {
+    fn new(re: &'r RE, haystack: &'h str) -> Self {
+        let groups = re.backrefs.as_ref().map_or(0, |backrefs| backrefs.len());
+        let mut backrefs = false;
+        let steps = STEPS_PER_NODE_AND_BYTE
+            .saturating_mul(size(&re.rtype, &mut backrefs))
+            .saturating_mul(haystack.len() + 1)
+            .saturating_add(MIN_STEPS);
+        Backtracker {
+            pattern: then(Task::Seq(&re.rtype), None),
+            haystack,
+            slots: vec![None; groups + 1],
+            stack: vec![],
+            steps,
+            memo: (!backrefs).then(|| Memo::new(haystack.len() + 1)),
+            depth: 0,
+        }
+    }
+
+    fn match_at(&mut self, at: usize) -> Result<Option<Slots>, MatchError> {
+        self.slots.fill(None);
+        let Some(end) = self.run(at, self.pattern.clone(), None)? else {
+            return Ok(None);
+        };
+        let mut slots = self.slots.clone();
+        slots[0] = Some(at..end);
+        Ok(Some(slots))
+    }
+
+    // Matches the continuation `k` from `at`, up to `end` if it's given. Returns where the first
+    // way it matches ends, keeping the captures it set. The choice points it pushed are dropped
+    // then, so that it's never backtracked into, but the captures are still undone when the
+    // pattern fails further on.
+    fn run(
+        &mut self,
+        mut at: usize,
+        mut k: Cont<'r>,
+        end: Option<usize>,
+    ) -> Result<Option<usize>, MatchError> {
+        let base = self.stack.len();
+        loop {
+            self.steps = self
+                .steps
+                .checked_sub(1)
+                .ok_or(MatchError::BacktrackLimit)?;
+            let matched = match k.take() {
+                Some(link) => {
+                    k = link.next.clone();
+                    !self.seen(&link, at) && self.task(link.task, &mut at, &mut k)?
+                }
+                None if end.map_or(true, |end| at == end) => {
+                    self.drop_choices(base);
+                    return Ok(Some(at));
+                }
+                None => false,
+            };
+            if matched {
+                continue;
+            }
+            loop {
+                if self.stack.len() == base {
+                    return Ok(None);
+                }
+                match self.stack.pop().expect("stack is above its base") {
+                    Frame::Choice(pos, cont) => {
+                        at = pos;
+                        k = cont;
+                        break;
+                    }
+                    Frame::RestoreSlot(group, span) => self.slots[group] = span,
+                }
+            }
+        }
+    }
+
+    // Drops the choice points above the stack length `base`, keeping the captures to undo
+    fn drop_choices(&mut self, base: usize) {
+        let mut kept = base;
+        for i in base..self.stack.len() {
+            if !matches!(self.stack[i], Frame::Choice(..)) {
+                self.stack.swap(kept, i);
+                kept += 1;
+            }
+        }
+        self.stack.truncate(kept);
+    }
+
+    // Undoes the captures set since the stack was `base` long
+    fn undo_captures(&mut self, base: usize) {
+        while self.stack.len() > base {
+            if let Some(Frame::RestoreSlot(group, span)) = self.stack.pop() {
+                self.slots[group] = span;
+            }
+        }
+    }
+
+    // Whether the repetition `link` goes on with was already run from `at` in the current run,
+    // remembering that it was otherwise. Repetitions are where the ways to match multiply, the
+    // other tasks aren't remembered. Inside of an atomic group the rest of the pattern failing
+    // after the first way the group matched isn't the same as that way failing, the group would
+    // then match another way: nothing is remembered there.
+    fn seen(&mut self, link: &Link<'r>, at: usize) -> bool {
+        let Some(memo) = self.memo.as_mut() else {
+            return false;
+        };
+        if !matches!(link.task, Task::Repeat(..)) || link.atomic {
+            return false;
+        }
+        let id = memo.id(link, at);
+        let len = memo.len;
+        !memo.visited[self.depth].insert(id, at, len)
+    }
+
+    // Runs a task at `at`, moving it past what was matched and adding what's left to match to `k`.
+    // Returns false if the task can't match there.
+    fn task(
+        &mut self,
+        task: Task<'r>,
+        at: &mut usize,
+        k: &mut Cont<'r>,
+    ) -> Result<bool, MatchError> {
+        match task {
+            Task::Seq(rtypes) => {
+                let Some((rtype, rest)) = rtypes.split_first() else {
+                    return Ok(true);
+                };
+                if !rest.is_empty() {
+                    *k = then(Task::Seq(rest), k.take());
+                }
+                self.node(rtype, at, k)
+            }
+            Task::Repeat(rtype, quantifier, count, start) => {
+                // an optional iteration matching the empty string makes no progress, the
+                // repetition stops after it and the rest of the pattern goes on like in Perl. The
+                // mandatory ones all run, the next one could still match something.
+                if *at == start && count > quantifier.min {
+                    return Ok(true);
+                }
+                Ok(self.repeat(rtype, quantifier, count, *at, k))
+            }
+            Task::CaptureEnd(group, start) => {
+                let previous = self.slots[group].replace(start..*at);
+                self.stack.push(Frame::RestoreSlot(group, previous));
+                Ok(true)
+            }
+            Task::AtomicEnd(len) => {
+                // only the first way the group matched is ever tried, but captures set inside of
+                // it still have to be undone if the rest of the pattern fails
+                self.drop_choices(len);
+                Ok(true)
+            }
+        }
+    }
+
+    fn node(
+        &mut self,
+        rtype: &'r RType,
+        at: &mut usize,
+        k: &mut Cont<'r>,
+    ) -> Result<bool, MatchError> {
+        let matched = match rtype {
+            RType::Repeat(rtype, quantifier) => self.repeat(rtype, quantifier, 0, *at, k),
+            RType::AltOr(branches) => {
+                // the first branch is tried right away, the other ones in order when it fails
+                let (first, rest) = branches.split_first().expect("alternation has branches");
+                for branch in rest.iter().rev() {
+                    let cont = then(Task::Seq(branch), k.clone());
+                    self.stack.push(Frame::Choice(*at, cont));
+                }
+                *k = then(Task::Seq(first), k.take());
+                true
+            }
+            RType::Group(rtypes) => {
+                *k = then(Task::Seq(rtypes), k.take());
+                true
+            }
+            RType::Capture(group, rtypes) => {
+                let end = then(Task::CaptureEnd(*group, *at), k.take());
+                *k = then(Task::Seq(rtypes), end);
+                true
+            }
+            RType::Atomic(rtypes) => {
+                let end = then(Task::AtomicEnd(self.stack.len()), k.take());
+                *k = then(Task::Seq(rtypes), end);
+                true
+            }
+            RType::Anchor(anchor) => anchor.matches_at(self.haystack, *at),
+            RType::LookAhead(rtypes) | RType::NegLookAhead(rtypes) => {
+                let negated = matches!(rtype, RType::NegLookAhead(_));
+                self.look_around(rtypes, negated, iter::once(*at), None)?
+            }
+            RType::LookBehind(min_len, max_len, rtypes)
+            | RType::NegLookBehind(min_len, max_len, rtypes) => {
+                let negated = matches!(rtype, RType::NegLookBehind(..));
+                // closest starts first, only as far back as the group can match: a group of a
+                // fixed length is only matched once
+                let haystack = self.haystack;
+                let starts = iter::once(*at)
+                    .chain(haystack[..*at].char_indices().rev().map(|(start, _)| start))
+                    .take(max_len.saturating_add(1))
+                    .skip(*min_len);
+                self.look_around(rtypes, negated, starts, Some(*at))?
+            }
+            RType::BackRefs(bnum) | RType::BackRefsNoCase(bnum) => {
+                // a group which didn't take part in the match can't be referred to
+                let Some(Some(captured)) = self.slots.get(*bnum).cloned() else {
+                    return Ok(false);
+                };
+                let captured = &self.haystack[captured];
+                let end = if let RType::BackRefsNoCase(_) = rtype {
+                    self.caseless_prefix(captured, *at)
+                } else {
+                    self.haystack[*at..]
+                        .starts_with(captured)
+                        .then_some(*at + captured.len())
+                };
+                match end {
+                    Some(end) => {
+                        *at = end;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => {
+                let Some(c) = char_at(self.haystack, *at) else {
+                    return Ok(false);
+                };
+                *at += c.len_utf8();
+                rtype.matches_char(c)
+            }
+        };
+        Ok(matched)
+    }
+
+    // Zero-width assertion that `rtypes` matches (or doesn't if `negated`) from one of the `starts`,
+    // up to `end` if it's given. Like for an atomic group only the first way the group matches is
+    // tried. Captures set inside of a positive assertion are kept for the rest of the pattern.
+    fn look_around(
+        &mut self,
+        rtypes: &'r [RType],
+        negated: bool,
+        starts: impl Iterator<Item = usize>,
+        end: Option<usize>,
+    ) -> Result<bool, MatchError> {
+        let base = self.stack.len();
+        // the runs of the group remember what they tried apart from the enclosing run
+        self.depth += 1;
+        if let Some(memo) = self.memo.as_mut() {
+            if memo.visited.len() == self.depth {
+                memo.visited.push(Visited::default());
+            }
+            memo.visited[self.depth].clear();
+        }
+        let group = then(Task::Seq(rtypes), None);
+        let mut matched = false;
+        for start in starts {
+            if self.run(start, group.clone(), end)?.is_some() {
+                matched = true;
+                break;
+            }
+        }
+        self.depth -= 1;
+        if negated {
+            // a group which didn't match doesn't capture anything
+            self.undo_captures(base);
+        }
+        Ok(matched != negated)
+    }
+
+    // Returns where the haystack stops matching `captured` ignoring case, if it does at `at`. The
+    // other cases of a character can have a different UTF-8 length, like k and the Kelvin sign.
+    fn caseless_prefix(&self, captured: &str, mut at: usize) -> Option<usize> {
+        for expected in captured.chars() {
+            let c = char_at(self.haystack, at)?;
+            if c != expected && !other_cases(expected).contains(&c) {
+                return None;
+            }
+            at += c.len_utf8();
+        }
+        Some(at)
+    }
+
+    // Chooses between another iteration and the rest of the pattern, `count` being the number of
+    // iterations already matched before `at`
+    fn repeat(
+        &mut self,
+        rtype: &'r RType,
+        quantifier: &'r Quantifier,
+        count: u32,
+        at: usize,
+        k: &mut Cont<'r>,
+    ) -> bool {
+        let can_stop = count >= quantifier.min;
+        let can_continue = quantifier.max.map_or(true, |max| count < max);
+        let iterate = |k: Cont<'r>| {
+            let next = then(Task::Repeat(rtype, quantifier, count + 1, at), k);
+            then(Task::Seq(slice::from_ref(rtype)), next)
+        };
+        match (can_stop, can_continue) {
+            (true, true) if quantifier.greedy => {
+                self.stack.push(Frame::Choice(at, k.clone()));
+                *k = iterate(k.take());
+            }
+            (true, true) => {
+                let cont = iterate(k.clone());
+                self.stack.push(Frame::Choice(at, cont));
+            }
+            (false, true) => *k = iterate(k.take()),
+            (true, false) => {}
+            (false, false) => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::get_regex_pattern;
+
+    // leftmost match of `pattern`, with the span of every group
+    fn find(pattern: &str, haystack: &str) -> Option<Slots> {
+        search(&get_regex_pattern(pattern).unwrap(), haystack, 0, false).unwrap()
+    }
+
+    #[test]
+    fn backtracking_matrix() {
+        let cases = [
+            // quantifiers give back what the rest of the pattern needs
+            ("a+ab", "aaab", Some(0..4)),
+            (r"\d+\d", "12345", Some(0..5)),
+            (".+foo", "a foo b", Some(0..5)),
+            (".*foo.*bar", "foo bar foo", Some(0..7)),
+            ("a{2,4}a{2}", "aaaaa", Some(0..5)),
+            ("a{2,4}a{3}", "aaaa", None),
+            ("x*y*z*", "", Some(0..0)),
+            // later branches are tried when the rest fails after an earlier one matched
+            ("(a|ab)c", "abc", Some(0..3)),
+            ("(a|ab|abc)d", "abcd", Some(0..4)),
+            ("(a|ab)(c|bcd)(d*)", "abcd", Some(0..4)),
+            ("(ab|a)+b", "ab", Some(0..2)),
+            ("x(a|b)*bb", "xababb", Some(0..6)),
+            // lazy quantifiers inside groups
+            ("<(.+?)>", "<a><b>", Some(0..3)),
+            ("(a+?)+b", "aaab", Some(0..4)),
+            // no match at all
+            ("a+b", "aaaa", None),
+            ("(a|b)c", "abab", None),
+            ("(?>a+)ab", "aaab", None),
+        ];
+        for (pattern, haystack, expected) in cases {
+            assert_eq!(
+                find(pattern, haystack).map(|slots| slots[0].clone().unwrap()),
+                expected,
+                "{} on {:?}",
+                pattern,
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn capture_extents() {
+        let cases = [
+            ("(a+)(a+)", "aaaa", vec![Some(0..3), Some(3..4)]),
+            ("(a+?)(a+)", "aaaa", vec![Some(0..1), Some(1..4)]),
+            ("(a*)b", "aab", vec![Some(0..2)]),
+            ("(a|ab)(c|bcd)", "abcd", vec![Some(0..1), Some(1..4)]),
+            ("(x)?y", "y", vec![None]),
+            ("(a)|b", "b", vec![None]),
+            // the last iteration of a repeated group is what's captured
+            ("(a|b)+", "abba", vec![Some(3..4)]),
+            ("((a)|(b))+", "ab", vec![Some(1..2), Some(0..1), Some(1..2)]),
+            // an empty optional iteration is taken, then the repetition stops
+            ("(x?)?", "", vec![Some(0..0)]),
+            ("(^|b)?c?", "bc", vec![Some(0..0)]),
+            ("(a?)*", "", vec![Some(0..0)]),
+            ("(a|)+b", "ab", vec![Some(1..1)]),
+        ];
+        for (pattern, haystack, expected) in cases {
+            let slots = find(pattern, haystack).unwrap();
+            assert_eq!(slots[1..], expected, "{} on {:?}", pattern, haystack);
+        }
+    }
+
+    #[test]
+    fn backrefs_follow_backtracking() {
+        let cases = [
+            (r"(a+)b\1", "aaabaa", Some(1..6)),
+            (r"(a+)\1", "aaa", Some(0..2)),
+            (r"(\w+) \1", "hello hello", Some(0..11)),
+            (r"(\w+) \1", "hello help", None),
+            (r"(a|ab)\1c", "ababc", Some(0..5)),
+            // a group which didn't take part in the match doesn't match anything
+            (r"(a)?b\1", "b", None),
+            (r"((a)|b)\2", "bb", None),
+        ];
+        for (pattern, haystack, expected) in cases {
+            assert_eq!(
+                find(pattern, haystack).map(|slots| slots[0].clone().unwrap()),
+                expected,
+                "{} on {:?}",
+                pattern,
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn lookarounds() {
+        let cases = [
+            ("foo(?=bar)", "foobaz foobar", Some(7..10)),
+            ("foo(?!bar)", "foobar foobaz", Some(7..10)),
+            ("(?<=\\$)\\d+", "5 or $42", Some(6..8)),
+            ("(?<!\\$)\\b\\d+", "$5 or 42", Some(6..8)),
+            ("(?<=ab|c)d", "abd", Some(2..3)),
+            ("(?<=a.{2,3})x", "abcdx", Some(4..5)),
+            ("(?<=a.{2,3})x", "abcdex", None),
+            ("(?<=a.{0,9})x", "abcdefx", Some(6..7)),
+            ("(?<=x?)y|z", "z", Some(0..1)),
+            ("(?<=é)x", "éx", Some(2..3)),
+            ("^(?=.*\\d)(?=.*[a-z]).{6,}$", "abc123", Some(0..6)),
+            ("^(?=.*\\d)(?=.*[a-z]).{6,}$", "abcdef", None),
+            // a lookaround only matches one way, like an atomic group
+            ("(?=(a+))a*b\\1", "aaab", None),
+            ("(?=(a+))a\\1", "aaa", None),
+        ];
+        for (pattern, haystack, expected) in cases {
+            assert_eq!(
+                find(pattern, haystack).map(|slots| slots[0].clone().unwrap()),
+                expected,
+                "{} on {:?}",
+                pattern,
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn lookaround_captures() {
+        let slots = find("(?=(\\w+))\\w", "ab").unwrap();
+        assert_eq!(slots, vec![Some(0..1), Some(0..2)]);
+        let slots = find("(?<=(a))b", "ab").unwrap();
+        assert_eq!(slots, vec![Some(1..2), Some(0..1)]);
+        // a negative lookaround doesn't capture anything
+        let slots = find("(?!(a))b", "b").unwrap();
+        assert_eq!(slots, vec![Some(0..1), None]);
+    }
+
+    #[test]
+    fn long_haystacks_dont_overflow_the_stack() {
+        let haystack = format!("xx{}b", "a".repeat(200_000));
+        for (pattern, expected) in [
+            (r"(x)\1.*$", 0..haystack.len()),
+            ("(?>a*)b", 2..haystack.len()),
+            ("(?=a).*", 2..haystack.len()),
+            ("(?<=a)b", haystack.len() - 1..haystack.len()),
+            ("(?:a|b)+", 2..haystack.len()),
+        ] {
+            let slots = find(pattern, &haystack).unwrap();
+            assert_eq!(slots[0], Some(expected), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn first_characters() {
+        for (pattern, expected) in [
+            ("x(?=q)", Some(vec![RType::Ch('x')])),
+            ("(?<=a)b|^c", Some(vec![RType::Ch('b'), RType::Ch('c')])),
+            ("(a?)*b+", Some(vec![RType::Ch('a'), RType::Ch('b')])),
+            (r"(a)\1", Some(vec![RType::Ch('a')])),
+            // a match can start anywhere when it can be empty or start with a back-reference
+            ("a*|b", None),
+            (r"(a?)\1b", None),
+        ] {
+            let re = get_regex_pattern(pattern).unwrap();
+            let mut firsts = vec![];
+            let firsts = (first_chars(&re.rtype, &mut firsts) == Some(false))
+                .then(|| firsts.into_iter().cloned().collect::<Vec<_>>());
+            assert_eq!(firsts, expected, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn nested_quantifiers_dont_take_exponential_time() {
+        let haystack = "a".repeat(25);
+        // each loop is run once from a position
+        for pattern in [
+            r"(?=a)(a|a)*b",
+            r"(?:a+)+(?=b)",
+            r"(?=(a|a)*b)",
+            r"(?>(a|a)*)*b",
+        ] {
+            assert_eq!(find(pattern, &haystack), None, "{}", pattern);
+        }
+        assert_eq!(find(r"(?=a)(a|a)*$", &haystack).unwrap()[1], Some(24..25));
+        // which isn't enough with a back-reference, the search gives up
+        let re = get_regex_pattern(r"(a*)*\1b").unwrap();
+        assert_eq!(
+            search(&re, &haystack, 0, false),
+            Err(MatchError::BacktrackLimit)
+        );
+    }
+}
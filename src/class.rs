@@ -0,0 +1,138 @@
+use crate::unicode::{other_cases, other_cases_in};
+use crate::Property;
+
+/// A bracket expression like `[a-z_\d]` or `[^[:space:]]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharClass {
+    pub items: Vec<ClassItem>,
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassItem {
+    Ch(char),                   // single character
+    Range(char, char),          // inclusive range like a-z
+    Digit,                      // \d
+    Word,                       // \w
+    Space,                      // \s
+    Posix(Posix),               // named class like [:alpha:]
+    Property(Property),         // Unicode property like \p{Greek}, or \d, \w and \s in Unicode mode
+    Negated(Box<ClassItem>),    // complement of an item, like \D or [:^alpha:]
+    CaseFolded(Box<ClassItem>), // item matching any case of a character, like [:upper:] with (?i)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Posix {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Word,
+    Xdigit,
+}
+
+impl CharClass {
+    pub fn matches(&self, c: char) -> bool {
+        self.items.iter().any(|item| item.matches(c)) != self.negated
+    }
+
+    // Adds the other cases of its characters and ranges to the class, so that it matches them
+    // regardless of case. Named classes and properties, which are too big to list their
+    // characters, are folded when matching instead.
+    pub(crate) fn add_other_cases(&mut self) {
+        let mut others: Vec<char> = vec![];
+        for item in &mut self.items {
+            let range = match item {
+                ClassItem::Ch(c) => *c..=*c,
+                ClassItem::Range(start, end) => *start..=*end,
+                ClassItem::Posix(_) | ClassItem::Property(_) | ClassItem::Negated(_) => {
+                    let folded = ClassItem::CaseFolded(Box::new(item.clone()));
+                    *item = folded;
+                    continue;
+                }
+                _ => continue,
+            };
+            for (_, cases) in other_cases_in(range) {
+                others.extend_from_slice(cases);
+            }
+        }
+        others.sort_unstable();
+        others.dedup();
+        others.retain(|c| !self.items.iter().any(|item| item.matches(*c)));
+        self.items.extend(others.into_iter().map(ClassItem::Ch));
+    }
+}
+
+impl ClassItem {
+    pub fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Ch(ch) => *ch == c,
+            ClassItem::Range(start, end) => (*start..=*end).contains(&c),
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => is_word_char(c),
+            ClassItem::Space => is_space_char(c),
+            ClassItem::Posix(posix) => posix.matches(c),
+            ClassItem::Property(property) => property.matches(c),
+            ClassItem::Negated(item) => !item.matches(c),
+            ClassItem::CaseFolded(item) => {
+                item.matches(c) || other_cases(c).iter().any(|other| item.matches(*other))
+            }
+        }
+    }
+}
+
+impl Posix {
+    pub fn from_name(name: &str) -> Option<Posix> {
+        Some(match name {
+            "alnum" => Posix::Alnum,
+            "alpha" => Posix::Alpha,
+            "blank" => Posix::Blank,
+            "cntrl" => Posix::Cntrl,
+            "digit" => Posix::Digit,
+            "graph" => Posix::Graph,
+            "lower" => Posix::Lower,
+            "print" => Posix::Print,
+            "punct" => Posix::Punct,
+            "space" => Posix::Space,
+            "upper" => Posix::Upper,
+            "word" => Posix::Word,
+            "xdigit" => Posix::Xdigit,
+            _ => return None,
+        })
+    }
+
+    pub fn matches(&self, c: char) -> bool {
+        match self {
+            Posix::Alnum => c.is_ascii_alphanumeric(),
+            Posix::Alpha => c.is_ascii_alphabetic(),
+            Posix::Blank => c == ' ' || c == '\t',
+            Posix::Cntrl => c.is_ascii_control(),
+            Posix::Digit => c.is_ascii_digit(),
+            Posix::Graph => c.is_ascii_graphic(),
+            Posix::Lower => c.is_ascii_lowercase(),
+            Posix::Print => c.is_ascii_graphic() || c == ' ',
+            Posix::Punct => c.is_ascii_punctuation(),
+            Posix::Space => is_space_char(c),
+            Posix::Upper => c.is_ascii_uppercase(),
+            Posix::Word => is_word_char(c),
+            Posix::Xdigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+/// Characters matched by `\w`.
+pub fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Characters matched by `\s`.
+pub fn is_space_char(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0B' | '\x0C')
+}
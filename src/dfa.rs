@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::nfa::{Inst, Program, SparseSet};
+use crate::{is_word_char, pikevm, Anchor, Property, RE};
+
+type StateId = u32;
+
+// Transition which hasn't been computed yet
+const UNKNOWN: StateId = StateId::MAX;
+// State without any NFA thread left, the haystack can't match anymore once it's reached
+const DEAD: StateId = 0;
+// State reached once a thread matched, the haystack matches whatever comes next
+const MATCH: StateId = 1;
+
+// A search gives up on the DFA, and runs the Pike VM instead, when the cache keeps being flushed
+// without the states being reused for at least this many characters each
+const MIN_FLUSHES: usize = 3;
+const MIN_CHARS_PER_STATE: usize = 10;
+
+/// DFA answering whether a pattern matches a haystack, built lazily from the pattern's NFA: a
+/// state is only computed the first time the search needs it, and kept in a cache for the next
+/// characters and the next haystacks.
+///
+/// The cache holds up to `cache_capacity` bytes of states, it's flushed when it's full. Searches
+/// where the cached states aren't reused enough fall back to the Pike VM.
+#[derive(Debug, Clone)]
+pub struct LazyDfa {
+    program: Program,
+    cache: Cache,
+}
+
+/// States of a lazy DFA for a program, computed as the searches need them.
+#[derive(Debug, Clone)]
+pub(crate) struct Cache {
+    capacity: usize,
+    memory: usize,
+    states: Vec<State>,
+    index: HashMap<(Box<[usize]>, Option<char>), StateId>,
+    start: StateId,
+    set: SparseSet,
+    stack: Vec<usize>,
+}
+
+// Anchors can't be followed before knowing the characters around a position, so a state holds
+// the instructions the NFA threads are at before following them, along with the character before
+// the position.
+#[derive(Debug, Clone)]
+struct State {
+    pcs: Box<[usize]>,
+    before: Option<char>, // as given by `look_behind`, `None` at the start of the haystack
+    is_match_at_end: bool, // a thread reaches Match if the haystack ends here
+    ascii: Box<[StateId; 128]>, // transitions on ASCII characters
+    other: HashMap<char, StateId>,
+}
+
+impl LazyDfa {
+    pub const DEFAULT_CACHE_CAPACITY: usize = 2 << 20;
+
+    /// Returns `None` for patterns which need the backtracker, like the ones with
+    /// backreferences, and for the ones using `\Z`.
+    pub fn new(re: &RE) -> Option<LazyDfa> {
+        LazyDfa::with_cache_capacity(re, LazyDfa::DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_cache_capacity(re: &RE, cache_capacity: usize) -> Option<LazyDfa> {
+        let program = Program::compile(re)?;
+        if !is_supported(&program) {
+            return None;
+        }
+        let cache = Cache::new(&program, cache_capacity);
+        Some(LazyDfa { program, cache })
+    }
+
+    pub fn is_match(&mut self, haystack: &str) -> bool {
+        match self.cache.is_match(&self.program, haystack) {
+            Some(is_match) => is_match,
+            None => pikevm::search(&self.program, haystack, 0).is_some(),
+        }
+    }
+}
+
+// Whether the DFA can run `program`
+pub(crate) fn is_supported(program: &Program) -> bool {
+    // whether \Z holds depends on the two characters after the position
+    !program
+        .insts
+        .contains(&Inst::Assert(Anchor::EndBeforeNewline))
+}
+
+impl Cache {
+    pub(crate) fn new(program: &Program, capacity: usize) -> Self {
+        let mut cache = Cache {
+            capacity,
+            memory: 0,
+            states: vec![],
+            index: HashMap::new(),
+            start: DEAD,
+            set: SparseSet::default(),
+            stack: vec![],
+        };
+        cache.flush(program);
+        cache
+    }
+
+    // Drops every state but the dead, match and start ones
+    fn flush(&mut self, program: &Program) {
+        self.memory = 0;
+        self.states.clear();
+        self.index.clear();
+        for is_match in [false, true] {
+            self.states.push(State {
+                pcs: Box::new([]),
+                before: None,
+                is_match_at_end: is_match,
+                ascii: Box::new([if is_match { MATCH } else { DEAD }; 128]),
+                other: HashMap::new(),
+            });
+        }
+        self.start = self.add_state(program, vec![0], None);
+    }
+
+    // Returns `None` when the search should fall back to the Pike VM
+    pub(crate) fn is_match(&mut self, program: &Program, haystack: &str) -> Option<bool> {
+        let mut flushes = 0;
+        let mut since_flush = 0;
+        let mut state = self.start;
+        for c in haystack.chars() {
+            let mut next = self.states[state as usize].transition(c);
+            if next == UNKNOWN {
+                if self.memory > self.capacity {
+                    flushes += 1;
+                    if flushes >= MIN_FLUSHES
+                        && since_flush < MIN_CHARS_PER_STATE * self.states.len()
+                    {
+                        return None;
+                    }
+                    // the current state has to survive the flush
+                    let State { pcs, before, .. } = &self.states[state as usize];
+                    let (pcs, before) = (pcs.to_vec(), *before);
+                    self.flush(program);
+                    state = self.add_state(program, pcs, before);
+                    since_flush = 0;
+                }
+                next = self.next_state(program, state, c);
+            }
+            match next {
+                DEAD => return Some(false),
+                MATCH => return Some(true),
+                _ => {}
+            }
+            state = next;
+            since_flush += 1;
+        }
+        Some(self.states[state as usize].is_match_at_end)
+    }
+
+    fn next_state(&mut self, program: &Program, from: StateId, c: char) -> StateId {
+        self.set.clear();
+        let before = self.states[from as usize].before;
+        for i in 0..self.states[from as usize].pcs.len() {
+            let pc = self.states[from as usize].pcs[i];
+            self.closure(program, pc, before, Some(c));
+        }
+        let mut pcs = vec![];
+        let mut is_match = false;
+        for &pc in self.set.iter() {
+            match &program.insts[pc] {
+                Inst::Char(rtype) if rtype.matches_char(c) => pcs.push(pc + 1),
+                Inst::Match => is_match = true,
+                _ => {}
+            }
+        }
+        let to = if is_match {
+            MATCH
+        } else {
+            if !program.anchored {
+                // a match can start at any position
+                pcs.push(0);
+            }
+            self.add_state(program, pcs, look_behind(c))
+        };
+        let from = &mut self.states[from as usize];
+        if c.is_ascii() {
+            from.ascii[c as usize] = to;
+        } else {
+            from.other.insert(c, to);
+            self.memory += 2 * size_of::<(char, StateId)>();
+        }
+        to
+    }
+
+    // Adds to the set every instruction reached from `pc` without consuming a character, at a
+    // position between the characters `before` and `after`
+    fn closure(&mut self, program: &Program, pc: usize, before: Option<char>, after: Option<char>) {
+        self.stack.push(pc);
+        while let Some(pc) = self.stack.pop() {
+            if !self.set.insert(pc) {
+                continue;
+            }
+            match &program.insts[pc] {
+                Inst::Jmp(next) => self.stack.push(*next),
+                Inst::Split(first, second) => {
+                    self.stack.push(*second);
+                    self.stack.push(*first);
+                }
+                Inst::Save(_) => self.stack.push(pc + 1),
+                Inst::Assert(anchor) => {
+                    if anchor.matches_between(before, after) {
+                        self.stack.push(pc + 1);
+                    }
+                }
+                Inst::Char(_) | Inst::Match => {}
+            }
+        }
+    }
+
+    // Returns the state for the threads at `pcs` after `before`, creating it if it's new
+    fn add_state(
+        &mut self,
+        program: &Program,
+        mut pcs: Vec<usize>,
+        before: Option<char>,
+    ) -> StateId {
+        if pcs.is_empty() {
+            return DEAD;
+        }
+        // threads' priorities don't matter to know whether there's a match
+        pcs.sort_unstable();
+        pcs.dedup();
+        let key = (pcs.into_boxed_slice(), before);
+        if let Some(&id) = self.index.get(&key) {
+            return id;
+        }
+        self.set.clear();
+        for &pc in key.0.iter() {
+            self.closure(program, pc, before, None);
+        }
+        let is_match_at_end = self.set.iter().any(|&pc| program.insts[pc] == Inst::Match);
+        let id = self.states.len() as StateId;
+        self.memory += size_of::<State>() + size_of::<[StateId; 128]>() + 2 * size_of_val(&*key.0);
+        self.states.push(State {
+            pcs: key.0.clone(),
+            before,
+            is_match_at_end,
+            ascii: Box::new([UNKNOWN; 128]),
+            other: HashMap::new(),
+        });
+        self.index.insert(key, id);
+        id
+    }
+}
+
+impl State {
+    fn transition(&self, c: char) -> StateId {
+        if c.is_ascii() {
+            self.ascii[c as usize]
+        } else {
+            self.other.get(&c).copied().unwrap_or(UNKNOWN)
+        }
+    }
+}
+
+// Anchors only tell apart a newline, a word character, a character which is only a word
+// character in Unicode mode and any other character: the states after characters of the same
+// kind are the same
+fn look_behind(c: char) -> Option<char> {
+    Some(if c == '\n' {
+        '\n'
+    } else if is_word_char(c) {
+        'a'
+    } else if Property::PERL_WORD.matches(c) {
+        'é'
+    } else {
+        '\0'
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::get_regex_pattern;
+
+    fn dfa(pattern: &str, cache_capacity: usize) -> LazyDfa {
+        LazyDfa::with_cache_capacity(&get_regex_pattern(pattern).unwrap(), cache_capacity).unwrap()
+    }
+
+    #[test]
+    fn agrees_with_pikevm() {
+        let cases = [
+            ("a+ab", "aaab"),
+            ("a{2,4}a{3}", "aaaa"),
+            ("x*y*z*", ""),
+            ("(a|ab)(c|bcd)(d*)", "abcd"),
+            ("<(.+?)>", "<a>"),
+            ("<(.+?)>", "<>"),
+            ("^(a|b)c", "bc"),
+            ("^b", "ab"),
+            ("(a|b)c$", "acbc"),
+            ("(a|b)c$", "acbcd"),
+            ("^$", ""),
+            ("a$", "ba"),
+            ("é+$", "café"),
+            ("[^a]", "aaa"),
+            (r"\d{3}-\d{4}", "call 555-1234 now"),
+            (r"\d{3}-\d{4}", "call 555-123 now"),
+            ("^a$|b", "a"),
+            ("x$|^y", "yx"),
+            ("(?m)^b$", "a\nb\nc"),
+            ("(?m)^b$", "a\nbb\nc"),
+            ("(?m)a$\n^b", "a\nb"),
+            (r"\Aa|b\z", "cab"),
+            (r"\bfoo\b", "a foo"),
+            (r"\bfoo\b", "a foobar"),
+            (r"(?u)\Bé\b", "café"),
+            (r"\Bé\b", "café"),
+        ];
+        for (pattern, haystack) in cases {
+            let re = get_regex_pattern(pattern).unwrap();
+            let program = Program::compile(&re).unwrap();
+            assert_eq!(
+                dfa(pattern, LazyDfa::DEFAULT_CACHE_CAPACITY).is_match(haystack),
+                pikevm::search(&program, haystack, 0).is_some(),
+                "{} on {:?}",
+                pattern,
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn states_are_reused_across_haystacks() {
+        let mut dfa = dfa("ab+c", LazyDfa::DEFAULT_CACHE_CAPACITY);
+        assert!(dfa.is_match("xxabbbc"));
+        let states = dfa.cache.states.len();
+        assert!(dfa.is_match("abc"));
+        assert!(!dfa.is_match("abbbx"));
+        assert_eq!(dfa.cache.states.len(), states);
+    }
+
+    #[test]
+    fn full_cache_is_flushed() {
+        // every position of the last 12 characters needs its own state
+        let pattern = "a.{12}$";
+        let haystack = "ab".repeat(100);
+        let mut small = dfa(pattern, 4096);
+        assert!(small.is_match(&format!("{}a{}", haystack, "b".repeat(12))));
+        assert!(!small.is_match(&format!("{}a{}", haystack, "b".repeat(13))));
+        assert!(small.cache.memory <= 4096 + 2048);
+
+        // without room for any state the search always falls back to the Pike VM
+        let mut tiny = dfa(pattern, 0);
+        assert!(tiny.is_match(&format!("{}a{}", haystack, "b".repeat(12))));
+        assert!(!tiny.is_match("ab"));
+    }
+
+    #[test]
+    fn patterns_left_to_other_engines() {
+        assert!(LazyDfa::new(&get_regex_pattern(r"(a)\1").unwrap()).is_none());
+        assert!(LazyDfa::new(&get_regex_pattern(r"a\Z").unwrap()).is_none());
+    }
+}
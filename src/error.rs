@@ -0,0 +1,87 @@
+use std::ops::Range;
+
+use thiserror::Error;
+
+/// Error returned when a pattern can't be parsed.
+///
+/// `span` is the byte range of the offending part of the pattern, which can be used to point at
+/// it in a diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{kind} (at {}..{})", span.start, span.end)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub span: Range<usize>,
+}
+
+impl ParseError {
+    pub fn new(kind: ErrorKind, span: Range<usize>) -> Self {
+        ParseError { kind, span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ErrorKind {
+    #[error("missing closing ')' for opened '('")]
+    UnclosedGroup,
+    #[error("unknown group syntax after '(?'")]
+    UnknownGroupSyntax,
+    #[error("unknown flag '{0}'")]
+    UnknownFlag(char),
+    #[error("unopened ')'")]
+    UnopenedGroup,
+    #[error("groups are nested too deeply")]
+    NestingTooDeep,
+    #[error("missing closing ']' for opened '['")]
+    UnclosedClass,
+    #[error("unknown POSIX class [:{0}:]")]
+    UnknownPosixClass(String),
+    #[error("invalid range in bracket expression")]
+    InvalidClassRange,
+    #[error("quantifier can't be applied to another quantifier")]
+    RepeatedQuantifier,
+    #[error("repetition count is larger than 1000")]
+    RepetitionTooLarge,
+    #[error("repetition's minimum is larger than its maximum")]
+    InvalidRepetitionRange,
+    #[error("pattern can't end with an unescaped '\\'")]
+    TrailingBackslash,
+    #[error("unknown escape sequence '\\{0}'")]
+    UnknownEscape(char),
+    #[error("invalid hexadecimal escape")]
+    InvalidHexEscape,
+    #[error("{0:#X} isn't a valid unicode codepoint")]
+    InvalidCodepoint(u32),
+    #[error("invalid Unicode property, expected \\pX or \\p{{Name}}")]
+    InvalidPropertySyntax,
+    #[error("unknown Unicode property {0:?}")]
+    UnknownProperty(String),
+    #[error("back-reference can't be 0")]
+    ZeroBackref,
+    #[error("back-reference to undefined group {0}")]
+    UndefinedBackref(usize),
+    #[error("back-reference to a group defined after it")]
+    ForwardBackref,
+    #[error("back-reference to undefined relative group -{0}")]
+    UndefinedRelativeBackref(usize),
+    #[error("invalid back-reference, expected \\g{{N}} or \\g{{-N}}")]
+    InvalidBackrefSyntax,
+    #[error("invalid group name, expected letters, digits or '_' not starting with a digit")]
+    InvalidGroupName,
+    #[error("group name {0:?} is already used")]
+    DuplicateGroupName(String),
+    #[error("back-reference to undefined group name {0:?}")]
+    UndefinedGroupName(String),
+    #[error("lookbehind can't match an unbounded number of characters")]
+    UnboundedLookBehind,
+}
+
+/// Error returned when a search can't be completed.
+///
+/// Patterns with lookarounds, atomic groups or back-references are matched by backtracking,
+/// which can take exponential time on some of them. The search gives up rather than run for
+/// hours, the pattern and haystack are fine otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MatchError {
+    #[error("backtracking limit exceeded, the pattern takes too long to match this haystack")]
+    BacktrackLimit,
+}
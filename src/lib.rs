@@ -1,522 +1,338 @@
+mod backtrack;
+mod class;
+mod dfa;
+mod error;
+mod nfa;
+mod parser;
+mod pikevm;
+mod regex;
+mod unicode;
+
+use std::ops::Range;
+
+use backtrack::Slots;
+pub use class::{is_space_char, is_word_char, CharClass, ClassItem, Posix};
+pub use dfa::LazyDfa;
+pub use error::{ErrorKind, MatchError, ParseError};
+use nfa::Program;
+use parser::Parser;
+pub use regex::{Cache, CaptureMatches, Captures, Match, Matches, Regex};
+pub use unicode::Property;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum RType {
-    Ch(char),                                // character
-    Ccl(String, bool),                       // character group, +ve/-ve
-    Cgd,                                     // character class digit
-    Cgw,                                     // character class alphanumeric
-    Qplus(Box<RType>),                       // match one ore more time for previous RType
-    Qquestion(Box<RType>),                   // match zero or one time for previous RType
-    Wildcard,                                // match any character
-    AltOr(Box<Vec<RType>>, Box<Vec<RType>>), // match (a|b), a or b
-    BackRefs(u8),                            // match for backref like \1
-    Capture(Box<Vec<RType>>),                // capture multiple of RType within ()
+    Ch(char),                                     // character
+    Ccl(CharClass),                               // bracket expression like [a-z] or [^[:space:]]
+    Cgd,                                          // character class digit
+    Cgw,                                          // character class alphanumeric
+    Cgs,                                          // character class whitespace
+    Repeat(Box<RType>, Quantifier),               // match previous RType as the quantifier allows
+    Wildcard,                                     // match any character but a newline
+    AnyChar,                                      // match any character, like . under (?s)
+    AltOr(Vec<Vec<RType>>),                       // match a|b|..., first branch that matches
+    BackRefs(usize),                              // match for backref like \1
+    BackRefsNoCase(usize),                        // backref compared ignoring case, like (?i)\1
+    Capture(usize, Box<Vec<RType>>),              // capture group, numbered by its opening (
+    Group(Box<Vec<RType>>),                       // group which doesn't capture, like (?:...)
+    Atomic(Box<Vec<RType>>),                      // (?>...) or a*+, never gives back its match
+    Anchor(Anchor),                               // zero-width assertion like ^, $ or \A
+    LookAhead(Box<Vec<RType>>),                   // (?=...), the group matches from here
+    NegLookAhead(Box<Vec<RType>>),                // (?!...), the group doesn't match from here
+    LookBehind(usize, usize, Box<Vec<RType>>),    // (?<=...), group of min to max chars ending here
+    NegLookBehind(usize, usize, Box<Vec<RType>>), // (?<!...), the group doesn't end here
 }
 
-// NOTE: we'll be ignoring multi-line regex, so start/end anchor for newline is ignored read:
-// https://learn.microsoft.com/en-us/dotnet/standard/base-types/anchors-in-regular-expressions#start-of-string-only-a
-#[derive(Debug, Clone, PartialEq)]
-pub enum StringAnchor {
-    Start,
-    End,
-    None,
+impl RType {
+    // Whether `c` is matched by an RType which matches a single character, like Ch or Ccl
+    pub(crate) fn matches_char(&self, c: char) -> bool {
+        match self {
+            RType::Ch(ch) => *ch == c,
+            RType::Ccl(class) => class.matches(c),
+            RType::Cgd => c.is_ascii_digit(),
+            RType::Cgw => is_word_char(c),
+            RType::Cgs => is_space_char(c),
+            RType::Wildcard => c != '\n',
+            RType::AnyChar => true,
+            _ => unreachable!("{:?} doesn't match a single character", self),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct RE {
-    pub rtype: Vec<RType>,
-    pub anchor: StringAnchor,
-    pub backrefs: Option<Vec<(Vec<RType>, Option<String>)>>,
+/// Bounds of a repetition, `max` is `None` when there's no upper bound. A greedy quantifier
+/// prefers repeating as many times as possible, a lazy one (`*?`, `+?`, ...) as few as possible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantifier {
+    pub min: u32,
+    pub max: Option<u32>,
+    pub greedy: bool,
 }
 
-pub fn get_regex_pattern(pattern: &str) -> RE {
-    let mut re_pattern: Vec<RType> = vec![];
-    let mut string_anchor = StringAnchor::None;
-    let mut pattern = &pattern[..];
-    if pattern.starts_with('^') {
-        string_anchor = StringAnchor::Start;
-        pattern = &pattern[1..];
-    }
-    if pattern.ends_with('$') {
-        string_anchor = StringAnchor::End;
-        pattern = &pattern[..pattern.len() - 1];
-    }
-    let cpattern = pattern.chars().collect::<Vec<_>>();
-    let mut pidx: usize = 0; // pattern index
-    let mut idx = 0; // character index
-    let mut backrefs = vec![];
-    'out: loop {
-        if idx == cpattern.len() {
-            break 'out RE {
-                rtype: re_pattern,
-                anchor: string_anchor,
-                backrefs: Some(backrefs),
-            };
-        }
+impl Quantifier {
+    pub const STAR: Quantifier = Quantifier::new(0, None);
+    pub const PLUS: Quantifier = Quantifier::new(1, None);
+    pub const QUESTION: Quantifier = Quantifier::new(0, Some(1));
 
-        match cpattern[idx] {
-            '+' | '?' if pidx >= 1 => {
-                #[cfg(debug_assertions)]
-                {
-                    dbg!("match quantifier start");
-                    dbg!(idx);
-                    dbg!(pidx);
-                    dbg!(re_pattern.len());
-                    println!("{:?}", re_pattern);
-                }
-                match re_pattern[pidx - 1] {
-                    RType::Qplus(_) | RType::Qquestion(_) => {
-                        panic!("Quantifier can't be applied to another quantifier")
-                    }
-                    _ => {}
-                }
-                if cpattern[idx] == '+' {
-                    re_pattern[pidx - 1] = RType::Qplus(Box::new(re_pattern[pidx - 1].clone()));
-                } else {
-                    re_pattern[pidx - 1] = RType::Qquestion(Box::new(re_pattern[pidx - 1].clone()));
-                }
-                pidx -= 1;
-                #[cfg(debug_assertions)]
-                dbg!("match quantifier end");
-            }
-            '\\' => {
-                // TODO: add support to match '\' too, currently this logic ignores it
-                while '\\' == cpattern[idx + 1] {
-                    idx += 1;
-                }
-                idx += 1;
-                re_pattern.push(match cpattern[idx] {
-                    'd' => RType::Cgd,
-                    'w' => RType::Cgw,
-                    '+' => RType::Ch('+'),
-                    '1'..'9' => {
-                        // keeping it single digit for now
-                        RType::BackRefs(
-                            cpattern[idx]
-                                .to_digit(10)
-                                .expect("back-reference should be a number")
-                                as u8,
-                        )
-                    }
-                    _ => {
-                        #[cfg(debug_assertions)]
-                        println!("=> {}", cpattern[idx]);
-                        unreachable!();
-                    }
-                });
-            }
-            '(' => {
-                #[cfg(debug_assertions)]
-                dbg!("match capture group start");
-                let mut found = false;
-                let mut closing_dist = 0;
-                #[cfg(debug_assertions)]
-                dbg!(idx);
-                while idx + closing_dist < cpattern.len() {
-                    if cpattern[idx + closing_dist] == ')' {
-                        found = true;
-                        break;
-                    }
-                    closing_dist += 1;
-                }
-                #[cfg(debug_assertions)]
-                dbg!(closing_dist);
-                if !found {
-                    panic!("Invalid regex pattern provided. Missing closing ) for opened (")
-                }
-                let mut pipe_dist = 0;
-                found = false;
-                while idx + pipe_dist < cpattern.len() {
-                    if cpattern[idx + pipe_dist] == '|' {
-                        found = true;
-                        break;
-                    }
-                    pipe_dist += 1;
-                }
-                #[cfg(debug_assertions)]
-                dbg!(pipe_dist);
-                if found {
-                    let re_left =
-                        get_regex_pattern(&cpattern[idx + 1..].iter().collect::<String>());
-                    idx += pipe_dist;
-                    #[cfg(debug_assertions)]
-                    dbg!(idx);
-                    let re_right =
-                        get_regex_pattern(&cpattern[idx + 1..].iter().collect::<String>());
-                    idx += closing_dist - pipe_dist;
-                    #[cfg(debug_assertions)]
-                    {
-                        dbg!(idx);
-                        dbg!(cpattern.len());
-                    }
-                    let rtype = RType::AltOr(Box::new(re_left.rtype), Box::new(re_right.rtype));
-                    backrefs.push((vec![rtype.clone()], None));
-                    re_pattern.push(rtype);
-                } else {
-                    let re = get_regex_pattern(&cpattern[idx + 1..].iter().collect::<String>());
-                    idx += closing_dist;
-                    #[cfg(debug_assertions)]
-                    dbg!(idx);
-                    // TODO: push capture group here
-                    backrefs.push((re.rtype.clone(), None));
-                    re_pattern.push(RType::Capture(Box::new(re.rtype)));
-                }
-                #[cfg(debug_assertions)]
-                dbg!("match capture group end");
-            }
-            '|' | ')' => {
-                // should go back to '(' match block
-                return RE {
-                    rtype: re_pattern,
-                    anchor: StringAnchor::None,
-                    backrefs: Some(backrefs),
-                };
-            }
-            '[' => {
-                // let's assume that we'll find ']' later on always, for now
-                let mut gmode = true;
-                if idx + 1 < cpattern.len() {
-                    if cpattern[idx + 1] == '^' {
-                        gmode = false;
-                        idx += 1;
-                    }
-                }
-                let mut group = String::new();
-                while idx < cpattern.len() {
-                    if cpattern[idx] == ']' {
-                        break;
-                    }
-                    group.push(cpattern[idx]);
-                    idx += 1;
-                }
-                re_pattern.push(RType::Ccl(group, gmode));
-            }
-            '.' => re_pattern.push(RType::Wildcard),
-            _ => re_pattern.push(RType::Ch(cpattern[idx])),
-        };
-        pidx += 1;
-        idx += 1;
-        #[cfg(debug_assertions)]
-        {
-            println!("------------Reached End---------------");
-            dbg!(idx);
-            dbg!(pidx);
-            println!("rp: {:?}", &re_pattern);
-            println!("------------Ended---------------");
+    pub const fn new(min: u32, max: Option<u32>) -> Self {
+        Quantifier {
+            min,
+            max,
+            greedy: true,
         }
     }
-}
 
-fn match_quantifier(input_line: &str, re: &RE) -> usize {
-    let mut idx: usize = 0;
-    // NOTE: using match_here will not create cycle, because a quantifer will not have another
-    // quantifier as RType
-    assert_eq!(re.rtype.len(), 1);
-
-    #[cfg(debug_assertions)]
-    println!("+|? {:?}: {:?}", &input_line, &re);
-    match &re.rtype[0] {
-        RType::Qplus(rtype) | RType::Qquestion(rtype) => {
-            let mut new_re = RE {
-                rtype: vec![*rtype.clone()],
-                anchor: StringAnchor::None,
-                backrefs: None,
-            };
-            #[cfg(debug_assertions)]
-            println!("+|? rtype: {:?}, {:?}", re.rtype[0], &re);
-            if let RType::Qplus(_) = re.rtype[0] {
-                #[cfg(debug_assertions)]
-                println!("+|? [if] new_re: {:?}", &new_re);
-                while match_here(&input_line[idx..], &mut new_re).0 {
-                    idx += 1;
-                }
-            } else if let RType::Qquestion(_) = re.rtype[0] {
-                if match_here(&input_line[idx..], &mut new_re).0 {
-                    idx += 1;
-                }
-            }
+    pub const fn lazy(self) -> Self {
+        Quantifier {
+            greedy: false,
+            ..self
         }
-        _ => {}
     }
-    idx
 }
 
-fn match_here(input_line: &str, re_pattern: &mut RE) -> (bool, usize) {
-    let input_chars = input_line.chars().collect::<Vec<_>>();
-    let mut idx = 0;
-    let rtype_iter = re_pattern.rtype.iter().peekable();
-    #[cfg(debug_assertions)]
-    println!("[here] {:?}: {:?}", &input_line, re_pattern);
-    for rtype in rtype_iter {
-        if idx == input_chars.len() {
-            return (false, idx);
+/// Position a zero-width assertion matches at, without consuming anything. Lines end with a `\n`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Start,                  // \A, or ^ outside of multi-line mode: start of the haystack
+    End,                    // \z, or $ outside of multi-line mode: end of the haystack
+    EndBeforeNewline,       // \Z, end of the haystack or right before a newline ending it
+    LineStart,              // ^ in multi-line mode, start of the haystack or right after a newline
+    LineEnd,                // $ in multi-line mode, end of the haystack or right before a newline
+    WordBoundary,           // \b, between a \w character and a \W one (or an edge of the haystack)
+    NotWordBoundary,        // \B, anywhere \b doesn't match
+    UnicodeWordBoundary,    // \b in Unicode mode, where \w follows the Unicode definition
+    UnicodeNotWordBoundary, // \B in Unicode mode
+}
+
+impl Anchor {
+    // Whether the anchor holds at byte offset `at` of `haystack`
+    pub(crate) fn matches_at(&self, haystack: &str, at: usize) -> bool {
+        if *self == Anchor::EndBeforeNewline {
+            return matches!(&haystack[at..], "" | "\n");
         }
-        #[cfg(debug_assertions)]
-        println!("[here for] {:?}: {:?}", &input_line, rtype);
-        match rtype {
-            RType::Qplus(_) | RType::Qquestion(_) => {
-                #[cfg(debug_assertions)]
-                println!("[here for] calling +|? for rtype: {:?}", rtype);
-                let tidx = match_quantifier(
-                    &input_line[idx..],
-                    &RE {
-                        rtype: vec![rtype.clone()],
-                        anchor: StringAnchor::None, // match_quantifier doesn't need to know about StringAnchor
-                        backrefs: None,
-                    },
-                );
-                #[cfg(debug_assertions)]
-                println!("[here for] +|? tidx: {}", tidx);
-                if let RType::Qplus(_) = rtype {
-                    if tidx == 0 {
-                        return (false, idx);
-                    }
-                }
-                if tidx == 0 && idx != 0 {
-                    idx -= 1; // no match, no increase (idx += 1 will be done at the end)
-                } else if tidx > 0 {
-                    // there's no point in subtracting if there's no match
-                    idx += tidx - 1;
-                }
-            }
-            RType::AltOr(re_left, re_right) => {
-                let left_status = match_here(
-                    &input_line[idx..],
-                    &mut RE {
-                        rtype: re_left.as_ref().clone(),
-                        anchor: StringAnchor::None,
-                        backrefs: None,
-                    },
-                );
-                if !left_status.0 {
-                    let right_status = match_here(
-                        &input_line[idx..],
-                        &mut RE {
-                            rtype: re_right.as_ref().clone(),
-                            anchor: StringAnchor::None,
-                            backrefs: None,
-                        },
-                    );
-                    if !right_status.0 {
-                        return (false, idx + left_status.1);
-                    }
-                    if let Some(ref mut backrefs) = re_pattern.backrefs {
-                        for backref in backrefs.iter_mut() {
-                            match backref.1 {
-                                Some(_) => {}
-                                None => {
-                                    backref.1 =
-                                        Some(input_line[idx..idx + right_status.1].to_string());
-                                    break;
-                                }
-                            }
-                        }
-                        #[cfg(debug_assertions)]
-                        println!("new backrefs: {:?}", re_pattern.backrefs.as_ref().unwrap());
-                    }
-                    if right_status.1 > 0 {
-                        idx += right_status.1 - 1;
-                    }
-                } else {
-                    if let Some(ref mut backrefs) = re_pattern.backrefs {
-                        for backref in backrefs.iter_mut() {
-                            match backref.1 {
-                                Some(_) => {}
-                                None => {
-                                    backref.1 =
-                                        Some(input_line[idx..idx + left_status.1].to_string());
-                                    break;
-                                }
-                            }
-                        }
-                        #[cfg(debug_assertions)]
-                        println!("new backrefs: {:?}", re_pattern.backrefs.as_ref().unwrap());
-                    }
-                    if left_status.1 > 0 {
-                        idx += left_status.1 - 1;
-                    }
-                }
-            }
-            RType::Capture(cg) => {
-                #[cfg(debug_assertions)]
-                println!(
-                    "Capture idx: {}, cg: {:?}, input_line: {}",
-                    idx,
-                    &cg,
-                    &input_line[idx..]
-                );
-                let match_status = match_here(
-                    &input_line[idx..],
-                    &mut RE {
-                        rtype: cg.as_ref().clone(),
-                        anchor: StringAnchor::None,
-                        backrefs: None,
-                    },
-                );
-                if !match_status.0 {
-                    return (false, idx + match_status.1);
-                }
-                #[cfg(debug_assertions)]
-                println!("Captured: {:?}", &input_line[idx..idx + match_status.1]);
-                if let Some(ref mut backrefs) = re_pattern.backrefs {
-                    for backref in backrefs.iter_mut() {
-                        match backref.1 {
-                            Some(_) => {}
-                            None => {
-                                backref.1 = Some(input_line[idx..idx + match_status.1].to_string());
-                                break;
-                            }
-                        }
-                    }
-                    #[cfg(debug_assertions)]
-                    println!("new backrefs: {:?}", re_pattern.backrefs.as_ref().unwrap());
-                }
-                if match_status.1 > 0 {
-                    idx += match_status.1 - 1;
-                }
-            }
-            RType::BackRefs(bnum) => {
-                if *bnum == 0 {
-                    panic!("Backref can't be 0");
-                }
-                if let Some(backrefs) = re_pattern.backrefs.as_ref() {
-                    if *bnum - 1 >= backrefs.len() as u8 {
-                        panic!("Not enough capture groups for backref count: {}", *bnum)
-                    }
-                    #[cfg(debug_assertions)]
-                    println!(
-                        "Backrefs idx: {}, bnum: {:?}, backref: {:?}, input_line: {}",
-                        idx,
-                        bnum,
-                        &backrefs,
-                        &input_line[idx..]
-                    );
-                    let backref = &backrefs[(*bnum - 1) as usize];
-                    if let Some(captured_string) = backref.1.as_ref() {
-                        if idx + captured_string.len() > input_line.len() {
-                            panic!(
-                                "String length not enough to match capture group.
-                                idx: {}, captured_string.len(): {}, input_line.len(): {}, bnum: {}",
-                                idx,
-                                captured_string.len(),
-                                input_line.len(),
-                                *bnum
-                            );
-                        }
-                        if !captured_string.eq(&input_line[idx..idx + captured_string.len()]) {
-                            #[cfg(debug_assertions)]
-                            println!(
-                                "Failed match, captured_string: {}, actual_string: {}",
-                                captured_string,
-                                &input_line[idx..idx + captured_string.len()]
-                            );
-                            return (false, idx + captured_string.len());
-                        }
-                        if captured_string.len() > 0 {
-                            idx += captured_string.len() - 1;
-                        }
-                    }
-                }
-            }
-            RType::Ch(c) if &input_chars[idx] != c => {
-                return (false, idx);
-            }
-            RType::Ccl(group, mode) => {
-                if *mode {
-                    let mut is_match = false;
-                    for cg in group.chars() {
-                        if cg == input_chars[idx] {
-                            is_match = true;
-                            break;
-                        }
-                    }
-                    if !is_match {
-                        return (false, idx);
-                    }
-                } else {
-                    // for when mode is not true, will continue later
-                    let mut is_match = true;
-                    for cg in group.chars() {
-                        if cg == input_chars[idx] {
-                            is_match = false;
-                            break;
-                        }
-                    }
-                    if !is_match {
-                        return (false, idx);
-                    }
-                }
-            }
-            RType::Cgd => {
-                if !input_chars[idx].is_ascii_digit() {
-                    return (false, idx);
-                }
+        self.matches_between(haystack[..at].chars().next_back(), char_at(haystack, at))
+    }
+
+    // Whether the anchor holds between the characters `before` and `after` a position, `None`
+    // standing for the edges of the haystack. \Z can't be told from them, it depends on
+    // whether the haystack ends after a newline.
+    pub(crate) fn matches_between(&self, before: Option<char>, after: Option<char>) -> bool {
+        match self {
+            Anchor::Start => before.is_none(),
+            Anchor::End => after.is_none(),
+            Anchor::LineStart => matches!(before, None | Some('\n')),
+            Anchor::LineEnd => matches!(after, None | Some('\n')),
+            Anchor::WordBoundary | Anchor::NotWordBoundary => {
+                let is_word = |c: Option<char>| c.is_some_and(is_word_char);
+                (is_word(before) != is_word(after)) == (*self == Anchor::WordBoundary)
             }
-            RType::Cgw => {
-                if !input_chars[idx].is_ascii_alphanumeric() {
-                    return (false, idx);
-                }
+            Anchor::UnicodeWordBoundary | Anchor::UnicodeNotWordBoundary => {
+                let is_word = |c: Option<char>| c.is_some_and(|c| Property::PERL_WORD.matches(c));
+                (is_word(before) != is_word(after)) == (*self == Anchor::UnicodeWordBoundary)
             }
-            RType::Wildcard => {} // do nothing
-            _ => {}
+            Anchor::EndBeforeNewline => unreachable!("\\Z depends on more than two characters"),
         }
-        idx += 1;
     }
-    if idx < input_chars.len() {
-        if let StringAnchor::End = re_pattern.anchor {
-            return (false, idx);
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RE {
+    pub rtype: Vec<RType>,
+    pub backrefs: Option<Vec<(Range<usize>, Option<String>)>>, // each group's span and capture
+    pub group_names: Vec<Option<String>>, // name of every capture group, like in (?<name>...)
+}
+
+/// Options changing how a pattern is parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flags {
+    pub unicode: bool,          // (?u), \d, \w and \s follow the Unicode definitions
+    pub case_insensitive: bool, // -i or (?i), letters match regardless of their case
+    pub multi_line: bool,       // (?m), ^ and $ match at the start and end of every line
+    pub dot_matches_new_line: bool, // (?s), . matches newlines too
+    pub ignore_whitespace: bool, // (?x), whitespace and # comments are ignored in the pattern
+}
+
+impl RE {
+    /// Compiles `pattern`, returning a [`ParseError`] pointing at the offending part of the
+    /// pattern if it's invalid.
+    pub fn new(pattern: &str) -> Result<RE, ParseError> {
+        get_regex_pattern(pattern)
+    }
+
+    /// Same as [`RE::new`], with `flags` instead of the default ones.
+    pub fn with_flags(pattern: &str, flags: Flags) -> Result<RE, ParseError> {
+        Parser::new(pattern, flags).parse()
+    }
+
+    /// Number of the capture group called `name`, groups being numbered from 1 by their opening
+    /// parenthesis.
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        let slot = self
+            .group_names
+            .iter()
+            .position(|other| other.as_deref() == Some(name))?;
+        Some(slot + 1)
+    }
+
+    /// Name of capture group `index`, if it has one.
+    pub fn group_name(&self, index: usize) -> Option<&str> {
+        self.group_names.get(index.checked_sub(1)?)?.as_deref()
+    }
+
+    // Whether the pattern can only match at the start of the haystack, like `^abc`
+    pub(crate) fn is_anchored_start(&self) -> bool {
+        self.rtype.first() == Some(&RType::Anchor(Anchor::Start))
+    }
+}
+
+pub fn get_regex_pattern(pattern: &str) -> Result<RE, ParseError> {
+    Parser::new(pattern, Flags::default()).parse()
+}
+
+// Character starting at byte offset `at` of `haystack`. Positions in a haystack are always byte
+// offsets, on a char boundary, whatever the engine: they can be used to slice it as is.
+pub(crate) fn char_at(haystack: &str, at: usize) -> Option<char> {
+    debug_assert!(
+        haystack.is_char_boundary(at),
+        "{} isn't a char boundary",
+        at
+    );
+    haystack[at..].chars().next()
+}
+
+// Stores the strings captured by every group in `re.backrefs`, or clears them without a match
+fn store_captures(re: &mut RE, haystack: &str, slots: Option<&Slots>) {
+    if let Some(backrefs) = re.backrefs.as_mut() {
+        for (i, backref) in backrefs.iter_mut().enumerate() {
+            let span = slots.and_then(|slots| slots[i + 1].clone());
+            backref.1 = span.map(|span| haystack[span].to_string());
         }
     }
-    (true, idx)
 }
 
-pub fn match_pattern(input_line: &str, mut re: &mut RE) -> bool {
+/// Whether `re` matches somewhere in `input_line`, storing what its groups captured in
+/// `re.backrefs`. The pattern is compiled again by every call: to match many lines, compile it
+/// once into a [`Regex`] and search every line with the same [`Cache`], which keeps the states
+/// of the lazy DFA from one line to the next.
+pub fn match_pattern(input_line: &str, re: &mut RE) -> Result<bool, MatchError> {
     if input_line.is_empty() {
-        false
-    } else if let StringAnchor::Start = re.anchor {
-        match_here(input_line, &mut re).0
-    } else {
-        if match_pattern(&input_line[1..], re) {
-            return true;
-        }
-        if !match_here(input_line, &mut re).0 {
-            if let Some(backrefs) = re.backrefs.as_mut() {
-                for backref in backrefs.iter_mut() {
-                    backref.1 = None;
-                }
-            }
-            return false;
-        }
-        true
+        return Ok(false);
     }
+    let program = Program::compile(re);
+    let mut cache = Cache::new(program.as_ref(), LazyDfa::DEFAULT_CACHE_CAPACITY);
+    let slots = regex::search_at(re, program.as_ref(), &mut cache, input_line, 0)?;
+    store_captures(re, input_line, slots.as_ref());
+    Ok(slots.is_some())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    // Finds the leftmost match of `re` in `haystack`, only at its start if `anchored`
+    fn search(re: &RE, haystack: &str, anchored: bool) -> Option<Slots> {
+        let anchored = anchored || re.is_anchored_start();
+        match Program::compile(re) {
+            Some(mut program) => {
+                program.anchored = anchored;
+                pikevm::search(&program, haystack, 0)
+            }
+            None => backtrack::search(re, haystack, 0, anchored).unwrap(),
+        }
+    }
+
+    // Matches `re_pattern` at the start of `input_line`, returning whether it matched and where the
+    // match ended. The captured strings are stored in `re_pattern.backrefs`.
+    fn match_here(input_line: &str, re_pattern: &mut RE) -> (bool, usize) {
+        let slots = search(re_pattern, input_line, true);
+        store_captures(re_pattern, input_line, slots.as_ref());
+        match slots {
+            Some(slots) => (true, slots[0].as_ref().map_or(0, |span| span.end)),
+            None => (false, 0),
+        }
+    }
+
+    // Checks whether each pattern matches its input line, compiled with `flags`
+    fn assert_matches_with(flags: Flags, cases: &[(&str, &str, bool)]) {
+        for &(pattern, input_line, expected) in cases {
+            let mut re_pattern = RE::with_flags(pattern, flags).unwrap();
+            assert_eq!(
+                match_pattern(input_line, &mut re_pattern).unwrap(),
+                expected,
+                "{} on {:?}",
+                pattern,
+                input_line
+            );
+        }
+    }
+
+    fn assert_matches(cases: &[(&str, &str, bool)]) {
+        assert_matches_with(Flags::default(), cases);
+    }
+
+    // Checks the error each pattern fails to compile with
+    fn assert_parse_errors(cases: &[(&str, ErrorKind, Range<usize>)]) {
+        for (pattern, kind, span) in cases {
+            assert_eq!(
+                RE::new(pattern),
+                Err(ParseError::new(kind.clone(), span.clone())),
+                "pattern: {}",
+                pattern
+            );
+        }
+    }
+
     #[test]
     fn quantifier_plus() {
-        let re = get_regex_pattern("o+");
-        assert_eq!(match_quantifier("oo", &re), 2);
+        let mut re = get_regex_pattern("o+").unwrap();
+        assert_eq!(match_here("oo", &mut re), (true, 2));
+    }
+
+    #[test]
+    fn possessive_quantifier_plus() {
+        let mut re_pattern = get_regex_pattern("a++a").unwrap();
+        assert!(!match_pattern("aaa", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("a++b").unwrap();
+        assert!(match_pattern("aaab", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("a*+b").unwrap();
+        assert!(match_pattern("b", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("x\\d{2,3}+\\d").unwrap();
+        assert!(match_pattern("x1234", &mut re_pattern).unwrap());
+        assert!(!match_pattern("x123", &mut re_pattern).unwrap());
+        assert_parse_errors(&[("a+++", ErrorKind::RepeatedQuantifier, 3..4)]);
+    }
+
+    #[test]
+    fn atomic_group() {
+        let mut re_pattern = get_regex_pattern("(?>a+)b").unwrap();
+        assert!(match_pattern("aab", &mut re_pattern).unwrap());
+        assert_eq!(re_pattern.backrefs, Some(vec![]));
+        let mut re_pattern = get_regex_pattern("(?>.*)x").unwrap();
+        assert!(!match_pattern("abx", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("(?>a|ab)c").unwrap();
+        assert!(match_pattern("ac", &mut re_pattern).unwrap());
+        assert!(!match_pattern("abc", &mut re_pattern).unwrap());
+        // a quantifier on an atomic group is fine
+        let mut re_pattern = get_regex_pattern("(?>a)+c").unwrap();
+        assert!(match_pattern("aac", &mut re_pattern).unwrap());
+        assert_parse_errors(&[("(?'a)", ErrorKind::UnknownGroupSyntax, 0..3)]);
     }
 
     #[test]
     fn full_pattern_quantifier_plus() {
-        let mut re_pattern = get_regex_pattern("g+o+$");
+        let mut re_pattern = get_regex_pattern("g+o+$").unwrap();
         let input_line = "logs are good";
-        assert_eq!(match_pattern(&input_line, &mut re_pattern), false);
-        let mut re_pattern = get_regex_pattern("g+o+d$");
-        assert_eq!(match_pattern(&input_line, &mut re_pattern), true);
+        assert!(!match_pattern(input_line, &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("g+o+d$").unwrap();
+        assert!(match_pattern(input_line, &mut re_pattern).unwrap());
     }
 
     #[test]
     fn full_pattern_quantifier_question() {
-        let mut re_pattern = get_regex_pattern("g+l?o+d$");
+        let mut re_pattern = get_regex_pattern("g+l?o+d$").unwrap();
         let input_line = "logs are good";
-        assert_eq!(match_pattern(&input_line, &mut re_pattern), true);
-        let mut re_pattern = get_regex_pattern("ca?t");
+        assert!(match_pattern(input_line, &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("ca?t").unwrap();
         let input_line = "cat";
-        assert_eq!(match_pattern(&input_line, &mut re_pattern), true);
+        assert!(match_pattern(input_line, &mut re_pattern).unwrap());
     }
 
     #[test]
@@ -526,24 +342,25 @@ mod test {
             rtype: vec![
                 RType::Ch('e'),
                 RType::Wildcard,
-                RType::AltOr(
-                    Box::new(vec![RType::Qplus(Box::new(RType::Ch('g')))]),
-                    Box::new(vec![RType::Qquestion(Box::new(RType::Ch('h')))]),
+                RType::Capture(
+                    1,
+                    Box::new(vec![RType::AltOr(vec![
+                        vec![RType::Repeat(Box::new(RType::Ch('g')), Quantifier::PLUS)],
+                        vec![RType::Repeat(
+                            Box::new(RType::Ch('h')),
+                            Quantifier::QUESTION,
+                        )],
+                    ])]),
                 ),
-                RType::Qplus(Box::new(RType::Ch('o'))),
+                RType::Repeat(Box::new(RType::Ch('o')), Quantifier::PLUS),
                 RType::BackRefs(1),
                 RType::Ch('d'),
+                RType::Anchor(Anchor::End),
             ],
-            anchor: crate::StringAnchor::End,
-            backrefs: Some(vec![(
-                vec![RType::AltOr(
-                    Box::new(vec![RType::Qplus(Box::new(RType::Ch('g')))]),
-                    Box::new(vec![RType::Qquestion(Box::new(RType::Ch('h')))]),
-                )],
-                None,
-            )]),
+            backrefs: Some(vec![(2..9, None)]),
+            group_names: vec![None],
         };
-        let actual_re = get_regex_pattern(re_string);
+        let actual_re = get_regex_pattern(re_string).unwrap();
         assert_eq!(actual_re, expected_re);
     }
 
@@ -554,29 +371,705 @@ mod test {
             rtype: vec![
                 RType::Ch('e'),
                 RType::Wildcard,
-                RType::AltOr(
-                    Box::new(vec![RType::Qplus(Box::new(RType::Ch('g')))]),
-                    Box::new(vec![RType::Qquestion(Box::new(RType::Ch('h')))]),
+                RType::Capture(
+                    1,
+                    Box::new(vec![RType::AltOr(vec![
+                        vec![RType::Repeat(Box::new(RType::Ch('g')), Quantifier::PLUS)],
+                        vec![RType::Repeat(
+                            Box::new(RType::Ch('h')),
+                            Quantifier::QUESTION,
+                        )],
+                    ])]),
                 ),
-                RType::Capture(Box::new(vec![RType::Ch('l'), RType::Ch('d')])),
-                RType::Qplus(Box::new(RType::Ch('o'))),
+                RType::Capture(2, Box::new(vec![RType::Ch('l'), RType::Ch('d')])),
+                RType::Repeat(Box::new(RType::Ch('o')), Quantifier::PLUS),
                 RType::BackRefs(1),
                 RType::Ch('d'),
                 RType::BackRefs(2),
+                RType::Anchor(Anchor::End),
             ],
-            anchor: crate::StringAnchor::End,
-            backrefs: Some(vec![
-                (
-                    vec![RType::AltOr(
-                        Box::new(vec![RType::Qplus(Box::new(RType::Ch('g')))]),
-                        Box::new(vec![RType::Qquestion(Box::new(RType::Ch('h')))]),
-                    )],
-                    None,
-                ),
-                (vec![RType::Ch('l'), RType::Ch('d')], None),
-            ]),
+            backrefs: Some(vec![(2..9, None), (9..13, None)]),
+            group_names: vec![None, None],
         };
-        let actual_re = get_regex_pattern(re_string);
+        let actual_re = get_regex_pattern(re_string).unwrap();
         assert_eq!(actual_re, expected_re);
     }
+
+    #[test]
+    fn parse_errors_carry_kind_and_span() {
+        assert_parse_errors(&[
+            ("a(bc", ErrorKind::UnclosedGroup, 1..4),
+            ("a[bc", ErrorKind::UnclosedClass, 1..4),
+            ("ab+*", ErrorKind::RepeatedQuantifier, 3..4),
+            ("ab\\", ErrorKind::TrailingBackslash, 2..3),
+            ("a\\qb", ErrorKind::UnknownEscape('q'), 1..3),
+            ("(a)\\0", ErrorKind::ZeroBackref, 3..5),
+            ("(a)\\2", ErrorKind::UndefinedBackref(2), 3..5),
+            ("^é(x", ErrorKind::UnclosedGroup, 3..5),
+        ]);
+    }
+
+    #[test]
+    fn full_pattern_sibling_groups() {
+        let mut re_pattern = get_regex_pattern("(a|b)(c|d)e").unwrap();
+        assert!(match_pattern("xbde", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("((c)a)t").unwrap();
+        assert!(match_pattern("a cat", &mut re_pattern).unwrap());
+    }
+
+    #[test]
+    fn full_pattern_alternation() {
+        let mut re_pattern = get_regex_pattern("cat|dog|bird").unwrap();
+        assert!(match_pattern("a bird sings", &mut re_pattern).unwrap());
+        assert!(match_pattern("hot dog", &mut re_pattern).unwrap());
+        assert!(!match_pattern("cow", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("x(a|b|c|d)y").unwrap();
+        assert!(match_pattern("xdy", &mut re_pattern).unwrap());
+        assert!(!match_pattern("xey", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("x(a|)y").unwrap();
+        assert!(match_pattern("xy", &mut re_pattern).unwrap());
+        assert!(match_pattern("xay", &mut re_pattern).unwrap());
+    }
+
+    #[test]
+    fn backref_records_whichever_branch_matched() {
+        assert_matches(&[
+            (r"(a|bb|ccc)-\1", "a-a", true),
+            (r"(a|bb|ccc)-\1", "bb-bb", true),
+            (r"(a|bb|ccc)-\1", "ccc-ccc", true),
+            (r"(a|bb|ccc)-\1", "bb-ccc", false),
+        ]);
+    }
+
+    #[test]
+    fn captures_are_the_ones_of_the_last_line() {
+        let mut re_pattern = get_regex_pattern(r"(\d+)-x").unwrap();
+        assert!(match_pattern("a 12-x", &mut re_pattern).unwrap());
+        assert_eq!(
+            re_pattern.backrefs.as_ref().unwrap()[0].1.as_deref(),
+            Some("12")
+        );
+        assert!(!match_pattern("12-y", &mut re_pattern).unwrap());
+        assert_eq!(re_pattern.backrefs.as_ref().unwrap()[0].1, None);
+        assert!(match_pattern("3-x", &mut re_pattern).unwrap());
+        assert_eq!(re_pattern.backrefs.unwrap()[0].1.as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn non_capturing_groups_are_not_numbered() {
+        assert_matches(&[
+            (r"(?:a|b)(c)\1", "bcc", true),
+            (r"(?:a|b)(c)\1", "bcb", false),
+            (r"(?:ab)+(x)\1", "ababxx", true),
+            (r"(?:(a)|b)\1", "aa", true),
+            (r"(?:x(?:y))(z)\1", "xyzz", true),
+            ("(?:)a", "a", true),
+        ]);
+        let mut re_pattern = get_regex_pattern(r"(?:(a)|b)(?:c)(d)").unwrap();
+        assert!(match_pattern("bcd", &mut re_pattern).unwrap());
+        assert_eq!(
+            re_pattern.backrefs.map(|backrefs| backrefs
+                .into_iter()
+                .map(|(_, captured)| captured)
+                .collect::<Vec<_>>()),
+            Some(vec![None, Some("d".to_string())])
+        );
+        assert_parse_errors(&[(r"(?:a)\1", ErrorKind::UndefinedBackref(1), 5..7)]);
+    }
+
+    #[test]
+    fn multi_digit_and_relative_backrefs() {
+        assert_matches(&[
+            (r"(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)\10", "abcdefghijj", true),
+            (r"(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)\10", "abcdefghija0", false),
+            (
+                r"(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)\g{1}0",
+                "abcdefghija0",
+                true,
+            ),
+            (r"(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)\9", "abcdefghiji", true),
+            (r"(a)(b)\g{2}", "abb", true),
+            (r"(a)(b)\g{-1}", "abb", true),
+            (r"(a)(b)\g{-2}", "aba", true),
+            (r"(a)(b)\g{-2}", "abb", false),
+            // relative to the groups opened before the reference, whether they're closed or not
+            (r"(a)(?:(b)\g{-1})", "abb", true),
+            (r"(?i)(a)\g{-1}", "aA", true),
+        ]);
+        assert_parse_errors(&[
+            (r"(a)\10", ErrorKind::UndefinedBackref(10), 3..6),
+            (r"(a)\g{2}", ErrorKind::UndefinedBackref(2), 3..8),
+            (r"(a)\g{-2}", ErrorKind::UndefinedRelativeBackref(2), 3..9),
+            (r"(a)\g{0}", ErrorKind::ZeroBackref, 3..8),
+            (r"(a)\g{-0}", ErrorKind::ZeroBackref, 3..9),
+            (r"(a)\g1", ErrorKind::InvalidBackrefSyntax, 3..5),
+            (r"(a)\g{x}", ErrorKind::InvalidBackrefSyntax, 3..8),
+            (r"(a)\g{1", ErrorKind::InvalidBackrefSyntax, 3..5),
+            (
+                r"(a)\99999999999999999999999",
+                ErrorKind::UndefinedBackref(usize::MAX),
+                3..27,
+            ),
+        ]);
+    }
+
+    #[test]
+    fn captures_are_numbered_by_opening_parenthesis() {
+        let mut re_pattern = get_regex_pattern("((a)(b(c)))(d)").unwrap();
+        assert!(match_pattern("abcd", &mut re_pattern).unwrap());
+        let captured: Vec<_> = re_pattern
+            .backrefs
+            .unwrap()
+            .into_iter()
+            .map(|(_, captured)| captured.unwrap())
+            .collect();
+        assert_eq!(captured, ["abc", "a", "bc", "c", "d"]);
+
+        // a backreference to a group which didn't take part in the match doesn't match
+        assert_matches(&[
+            (r"(a)?b\1", "b", false),
+            (r"(a)|b\1", "b", false),
+            (r"(?:(a)|b)\1", "b", false),
+            (r"(a\1)", "aa", false),
+            (r"(a)*b\1", "b", false),
+        ]);
+    }
+
+    #[test]
+    fn full_pattern_quantifier_star() {
+        let mut re_pattern = get_regex_pattern("ab*c").unwrap();
+        assert!(match_pattern("ac", &mut re_pattern).unwrap());
+        assert!(match_pattern("abbbc", &mut re_pattern).unwrap());
+        assert!(!match_pattern("abxc", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("ab*").unwrap();
+        assert!(match_pattern("a", &mut re_pattern).unwrap());
+    }
+
+    #[test]
+    fn full_pattern_counted_repetition() {
+        let mut re_pattern = get_regex_pattern("\\d{4}-\\d{2}-\\d{2}").unwrap();
+        assert!(match_pattern("date: 2024-01-31", &mut re_pattern).unwrap());
+        assert!(!match_pattern("date: 2024-1-31", &mut re_pattern).unwrap());
+        assert_matches(&[
+            ("xa{2}y", "xaay", true),
+            ("xa{2}y", "xaaay", false),
+            ("xa{2,}y", "xaaaay", true),
+            ("xa{2,}y", "xay", false),
+            ("xa{1,3}y", "xaaay", true),
+            ("xa{1,3}y", "xaaaay", false),
+            ("xa{0}y", "xy", true),
+        ]);
+    }
+
+    #[test]
+    fn malformed_braces_are_literals() {
+        assert_matches(&[
+            ("a{", "a{", true),
+            ("a{x}", "a{x}", true),
+            ("a{,2}", "a{,2}", true),
+            ("{2}", "{2}", true),
+        ]);
+        assert_parse_errors(&[
+            ("a{3,1}", ErrorKind::InvalidRepetitionRange, 1..6),
+            ("a{99999999999}", ErrorKind::RepetitionTooLarge, 1..14),
+            ("a{2,1001}", ErrorKind::RepetitionTooLarge, 1..9),
+            ("a{2}*", ErrorKind::RepeatedQuantifier, 4..5),
+        ]);
+        assert!(RE::new("a{1000}").is_ok());
+    }
+
+    #[test]
+    fn lazy_quantifiers_prefer_shortest_match() {
+        let cases = [
+            ("<.+?>", "<a><b>", 3),
+            ("<.+>", "<a><b>", 6),
+            ("a*?", "aaa", 0),
+            ("a+?", "aaa", 1),
+            ("a??", "a", 0),
+            ("a{2,4}?", "aaaa", 2),
+            // lazy quantifiers still extend when the rest of the pattern needs it
+            ("<.+?>x", "<a>b>x", 6),
+            ("a??b", "ab", 2),
+            ("\\d{2,4}?-", "1234-", 5),
+        ];
+        for (pattern, input_line, len) in cases {
+            let mut re_pattern = get_regex_pattern(pattern).unwrap();
+            assert_eq!(
+                match_here(input_line, &mut re_pattern),
+                (true, len),
+                "{} on {}",
+                pattern,
+                input_line
+            );
+        }
+    }
+
+    #[test]
+    fn greedy_quantifiers_give_back() {
+        let mut re_pattern = get_regex_pattern("a+ab").unwrap();
+        assert!(match_pattern("aaab", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern("\\d+\\d").unwrap();
+        assert!(match_pattern("42", &mut re_pattern).unwrap());
+        let mut re_pattern = get_regex_pattern(".*foo$").unwrap();
+        assert!(match_pattern("a foo", &mut re_pattern).unwrap());
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert_matches(&[
+            ("[a-z]", "m", true),
+            ("[a-z]", "-", false),
+            ("[a-z]", "M", false),
+            ("[a-cx-z0-9]", "y", true),
+            ("[a-cx-z0-9]", "5", true),
+            ("[a-cx-z0-9]", "d", false),
+            ("[\\]]", "]", true),
+            ("[]a]", "]", true),
+            ("[^]a]", "]", false),
+            ("[^]a]", "b", true),
+            ("[^abc]", "[", true),
+            ("[^abc]", "a", false),
+            ("[a-]", "-", true),
+            ("[-a]", "-", true),
+            ("[\\d_]", "_", true),
+            ("[\\d_]", "7", true),
+            ("[\\s]", "\t", true),
+            ("[\\D]", "7", false),
+            ("[[:alpha:]]", "q", true),
+            ("[[:alpha:]]", "1", false),
+            ("[[:space:]]", " ", true),
+            ("[[:^digit:]]", "1", false),
+            ("[[:upper:][:digit:]]", "Q", true),
+            ("[[:upper:][:digit:]]", "q", false),
+            ("[[a]", "[", true),
+        ]);
+    }
+
+    #[test]
+    fn invalid_bracket_expressions() {
+        assert_parse_errors(&[
+            ("[z-a]", ErrorKind::InvalidClassRange, 1..4),
+            ("x[a-\\d]", ErrorKind::InvalidClassRange, 2..6),
+            (
+                "[[:foo:]]",
+                ErrorKind::UnknownPosixClass("foo".to_string()),
+                1..8,
+            ),
+            ("[abc", ErrorKind::UnclosedClass, 0..4),
+            ("[]", ErrorKind::UnclosedClass, 0..2),
+        ]);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        assert_matches(&[
+            (r"a\.b", "a.b", true),
+            (r"a\.b", "axb", false),
+            (r"f\(x\)", "call f(x)", true),
+            (r"\[\]\{\}\|\?\*\+\^\$", "[]{}|?*+^$", true),
+            (r"C:\\dir", r"C:\dir", true),
+            (r"a\/b", "a/b", true),
+            (r"\s", "a b", true),
+            (r"\s", "ab", false),
+            (r"\S", "   ", false),
+            (r"\D", "123", false),
+            (r"\D", "12x", true),
+            (r"\W", "ab_1", false),
+            (r"\W", "ab-1", true),
+            (r"\w", "_", true),
+            (r"a\tb", "a\tb", true),
+            (r"a\nb", "a\nb", true),
+            (r"\r\f\v\a\e", "\r\x0C\x0B\x07\x1B", true),
+            (r"\x41\x{42}", "AB", true),
+            (r"[\x30-\x39]", "5", true),
+            (r"[\t\n]", "\t", true),
+            (r"[\.]", "a", false),
+        ]);
+    }
+
+    #[test]
+    fn invalid_escape_sequences() {
+        assert_parse_errors(&[
+            (r"\xZZ", ErrorKind::InvalidHexEscape, 0..4),
+            (r"\x4", ErrorKind::InvalidHexEscape, 0..3),
+            (r"\x{}", ErrorKind::InvalidHexEscape, 0..4),
+            (r"\x{41", ErrorKind::InvalidHexEscape, 0..5),
+            (r"\u12é4", ErrorKind::InvalidHexEscape, 0..7),
+            (r"\u{D800}", ErrorKind::InvalidCodepoint(0xD800), 0..8),
+            (r"\x{110000}", ErrorKind::InvalidCodepoint(0x110000), 0..10),
+            (r"\q", ErrorKind::UnknownEscape('q'), 0..2),
+            (r"[\q]", ErrorKind::UnknownEscape('q'), 1..3),
+            (r"\p", ErrorKind::InvalidPropertySyntax, 0..2),
+            (r"\p1", ErrorKind::InvalidPropertySyntax, 0..3),
+            (r"\p{Greek", ErrorKind::InvalidPropertySyntax, 0..8),
+            (
+                r"\P{Klingon}",
+                ErrorKind::UnknownProperty("Klingon".to_string()),
+                0..11,
+            ),
+            (r"[a\p{}]", ErrorKind::UnknownProperty("".to_string()), 2..6),
+        ]);
+    }
+
+    #[test]
+    fn quantified_groups_advance_by_their_width() {
+        let cases = [
+            ("(ab)+c", "ababc", Some(5)),
+            ("(ab){2}c", "ababc", Some(5)),
+            ("(ab)+c", "abac", None),
+            ("(a|bc)+d", "abcad", Some(5)),
+            ("(?>ab)+c", "ababc", Some(5)),
+            ("[ab]{3}", "bab", Some(3)),
+            (r"(ab)-\1+", "ab-ababab", Some(9)),
+            (r"(ab)-\1{2}x", "ab-abx", None),
+            (r"(ab)-\1?x", "ab-x", Some(4)),
+        ];
+        for (pattern, input_line, len) in cases {
+            let mut re_pattern = get_regex_pattern(pattern).unwrap();
+            let (is_match, matched) = match_here(input_line, &mut re_pattern);
+            assert_eq!(
+                is_match.then_some(matched),
+                len,
+                "{} on {}",
+                pattern,
+                input_line
+            );
+        }
+    }
+
+    #[test]
+    fn zero_length_iterations_terminate() {
+        assert_matches(&[
+            ("(a*)*b", "aaab", true),
+            ("(a|)+b", "b", true),
+            ("(a?){3}b", "ab", true),
+            ("(|a)*b", "aab", true),
+            ("()*x", "x", true),
+        ]);
+    }
+
+    #[test]
+    fn unicode_haystacks() {
+        assert_matches(&[
+            ("語", "日本語のテキスト", true),
+            ("^日本.", "日本語", true),
+            ("^.{3}", "日本語", true),
+            ("^.{3}", "日本", false),
+            ("^.", "💩", true),
+            ("💩+$", "I 💩💩", true),
+            (r"\x{1F4A9}", "💩", true),
+            ("[💩-💫]", "a💪", true),
+            // a family emoji is three emoji joined by two zero width joiners
+            ("^.{2}", "👨‍👩‍👧", true),
+            ("^.{6}", "👨‍👩‍👧", false),
+            ("caf.$", "café", true),
+            // a decomposed é is an e followed by a combining accent
+            ("caf.$", "cafe\u{301}", false),
+            ("é", "cafe\u{301}", false),
+            ("[à-ÿ]", "naïve", true),
+            ("[^a-z]", "abcé", true),
+            (r"^\w+$", "déjà", false),
+            ("^.$", "é", true),
+            ("^.$", "e\u{301}", false),
+            (r"(.)\1", "aé💩💩b", true),
+            (r"(.)\1", "aé💩b", false),
+        ]);
+    }
+
+    #[test]
+    fn unicode_captures() {
+        for (pattern, input_line, expected) in [
+            ("(日+)(.)", "今日日は", vec!["日日", "は"]),
+            (r"(.)\1", "aé💩💩b", vec!["💩"]),
+            ("(?>(é+))(.)", "ééà", vec!["éé", "à"]),
+            ("(.*)(e\u{301})", "cafe\u{301}", vec!["caf", "e\u{301}"]),
+        ] {
+            let mut re_pattern = get_regex_pattern(pattern).unwrap();
+            assert!(
+                match_pattern(input_line, &mut re_pattern).unwrap(),
+                "{}",
+                pattern
+            );
+            let captured: Vec<_> = re_pattern
+                .backrefs
+                .unwrap()
+                .into_iter()
+                .map(|(_, captured)| captured.unwrap())
+                .collect();
+            assert_eq!(captured, expected, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn unicode_properties() {
+        assert_matches(&[
+            (r"\p{Greek}+", "το λ", true),
+            (r"^\p{Greek}", "λόγος", true),
+            (r"^\p{Greek}", "logos", false),
+            (r"\pL\p{Nd}", "x٣", true),
+            (r"\P{L}", "abc", false),
+            (r"\P{L}", "ab1", true),
+            (r"\p{Lu}\p{Ll}", "Ça", true),
+            (r"\p{sc=Cyrillic}", "мир", true),
+            (r"[\p{Han}\p{Hiragana}]{3}", "今日は", true),
+            (r"[^\p{Latin}\s]", "hello world", false),
+            (r"[^\p{Latin}\s]", "hello мир", true),
+            (r"[\P{L}]", "abc", false),
+        ]);
+    }
+
+    #[test]
+    fn unicode_shorthand_classes() {
+        let unicode = Flags {
+            unicode: true,
+            ..Flags::default()
+        };
+        for (pattern, input_line, ascii_expected, unicode_expected) in [
+            (r"\d", "7", true, true),
+            (r"\d", "٣", false, true),
+            (r"\w", "é", false, true),
+            (r"\s", "\u{3000}", false, true),
+            (r"\D", "٣", true, false),
+            (r"[\w]", "ж", false, true),
+            (r"[^\W]", "ж", false, true),
+            (r"[\S]", "\u{A0}", true, false),
+        ] {
+            let mut ascii = RE::new(pattern).unwrap();
+            assert_eq!(
+                match_pattern(input_line, &mut ascii).unwrap(),
+                ascii_expected,
+                "{} on {:?}",
+                pattern,
+                input_line
+            );
+            let mut re_pattern = RE::with_flags(pattern, unicode).unwrap();
+            assert_eq!(
+                match_pattern(input_line, &mut re_pattern).unwrap(),
+                unicode_expected,
+                "{} on {:?} in Unicode mode",
+                pattern,
+                input_line
+            );
+        }
+    }
+
+    #[test]
+    fn case_insensitive_matching() {
+        let case_insensitive = Flags {
+            case_insensitive: true,
+            ..Flags::default()
+        };
+        assert_matches_with(
+            case_insensitive,
+            &[
+                ("hello", "HeLLo world", true),
+                ("^[a-c]+x", "AbCx", true),
+                ("[^a]", "aA", false),
+                (r"\x41", "a", true),
+                ("straße", "STRAẞE", true),
+                ("σ+", "ΣσςΣ", true),
+                ("k", "\u{212A}", true),
+                // only simple case folding: ß isn't the same as ss
+                ("ß", "ss", false),
+                (r"(\w+) \1", "Hello HELLO", true),
+                (r"(k) \1", "K \u{212A}", true),
+                (r"\d[[:upper:]]", "1a", true),
+                ("[[:lower:]]", "Q", true),
+                ("[^[:lower:]]", "Q", false),
+                (r"\p{Lu}", "a", true),
+                (r"\P{Lu}", "a", false),
+                ("[[:^upper:]]", "A", true),
+            ],
+        );
+    }
+
+    #[test]
+    fn inline_case_insensitive_flag() {
+        assert_matches(&[
+            ("(?i)hello", "HELLO", true),
+            ("hello", "HELLO", false),
+            ("a(?i)b", "aB", true),
+            ("a(?i)b", "AB", false),
+            // the flag stops applying at the end of its group, but goes on through alternations
+            ("((?i)a)b", "AB", false),
+            ("((?i)a)b", "Ab", true),
+            ("(?i)x|y", "Y", true),
+            ("(?>(?i)a)A", "aA", true),
+            // a quantifier right after the flags has nothing to repeat
+            ("a(?i)*", "a*", true),
+            (r"(?i)(a)\1", "aA", true),
+        ]);
+        assert_parse_errors(&[("a(?iq)", ErrorKind::UnknownFlag('q'), 4..5)]);
+    }
+
+    #[test]
+    fn inline_flag_groups() {
+        assert_matches(&[
+            ("a.b", "a\nb", false),
+            ("(?s)a.b", "a\nb", true),
+            ("(?s:a.)b", "a\nb", true),
+            ("(?s:a).b", "a\nb", false),
+            ("(?s)a(?-s).b", "a\nb", false),
+            ("(?i:a)b", "Ab", true),
+            ("(?i:a)b", "AB", false),
+            ("(?i:ab)+c", "aBAbc", true),
+            ("(?i)a(?-i)b", "AB", false),
+            ("(?i)a(?-i)b", "Ab", true),
+            ("(?i)a(?-i:b)c", "AbC", true),
+            ("(?i)a(?-i:b)c", "ABC", false),
+            ("(?is-i:A.)", "A\n", true),
+            ("(?is-i:A.)", "a\n", false),
+            ("(?m)a", "a", true),
+            ("(?u)\\w", "é", true),
+            // verbose mode
+            ("(?x) a b  c # the rest is a comment: d\n d", "abcd", true),
+            ("(?x)a\\ b", "a b", true),
+            ("(?x)a b", "a b", false),
+            ("(?x)a\\#b", "a#b", true),
+            ("(?x)[ ]", " ", true),
+            ("(?x)a +b", "aaab", true),
+            ("(?x: a | b )c", "bc", true),
+            ("(?x: a )  b", "a  b", true),
+        ]);
+    }
+
+    #[test]
+    fn anchors_anywhere() {
+        assert_matches(&[
+            ("^abc$", "abc", true),
+            ("^abc$", "abcabc", false),
+            ("^abc$", "xabc", false),
+            ("(^a|b$)", "cb", true),
+            ("(^a|b$)", "ab", true),
+            ("(^a|b$)", "ca", false),
+            ("x(^a|b$)", "xa", false),
+            ("a^b", "a^b", false),
+            ("a$b", "a$b", false),
+            (r"a\$b", "a$b", true),
+            (r"\^a", "^a", true),
+            ("^*a", "ba", true),
+            (r"\Aab\z", "ab", true),
+            (r"\Aab\z", "ab\n", false),
+            (r"ab\Z", "ab\n", true),
+            (r"ab\Z", "ab\n\n", false),
+            (r"ab\Z", "ab", true),
+            // ^ and $ only match at the edges of the haystack outside of multi-line mode
+            ("^b$", "a\nb\nc", false),
+            ("(?m)^b$", "a\nb\nc", true),
+            ("(?m)^b$", "a\nbb\nc", false),
+            ("(?m)^$", "a\n\nc", true),
+            ("(?m)a$\n^b", "a\nb", true),
+            ("(?m:^b)$", "a\nb\nc", false),
+            (r"(?m)\Ab", "a\nb", false),
+            (r"(?m)a\z", "a\nb", false),
+            (r"(?m)(\w+)$\n\1", "foo\nfoo", true),
+        ]);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_matches(&[
+            (r"\bfoo\b", "foo", true),
+            (r"\bfoo\b", "call foo(bar)", true),
+            (r"\bfoo\b", "foobar", false),
+            (r"\bfoo\b", "my_foo", false),
+            (r"\bfoo\b", "foo_bar foo2", false),
+            (r"\Boo\b", "foo", true),
+            (r"\Bfoo", "foo", false),
+            (r"\Bfoo", "xfoo", true),
+            (r"a\B", "a", false),
+            (r"\b", "", false),
+            (r"\B", " ", true),
+            (r"\b\w+\b$", "a b", true),
+            (r"^\b", "-a", false),
+            (r"(\b\w+\b) \1", "the the", true),
+            (r"(\b\w+\b) \1\b", "the theory", false),
+            // é is only a word character in Unicode mode, like for \w
+            (r"\bcaf\b", "café", true),
+            (r"(?u)\bcaf\b", "café", false),
+            (r"(?u)\bcafé\b", "un café", true),
+            (r"(?u)\Bé", "café", true),
+            (r"\Bé", "café", false),
+        ]);
+    }
+
+    #[test]
+    fn lookarounds() {
+        assert_matches(&[
+            (r"password=(?!\[REDACTED\])", "password=hunter2", true),
+            (r"password=(?!\[REDACTED\])", "password=[REDACTED]", false),
+            (r"(?<=\bid=)\d+", "uid=12 id=3", true),
+            (r"(?<=\bid=)\d+", "uid=12", false),
+            (r"(?<!un)happy", "unhappy", false),
+            (r"(?<!un)happy", "happy", true),
+            (r"(?i)(?<=A)b", "ab", true),
+            (r"(?=(\w))\1", "a", true),
+        ]);
+    }
+
+    #[test]
+    fn named_groups() {
+        let mut re_pattern = get_regex_pattern(r"(?P<key>\w+)=(?<value>\w+)(,)?").unwrap();
+        assert_eq!(re_pattern.group_index("key"), Some(1));
+        assert_eq!(re_pattern.group_index("value"), Some(2));
+        assert_eq!(re_pattern.group_index("other"), None);
+        assert_eq!(re_pattern.group_name(1), Some("key"));
+        assert_eq!(re_pattern.group_name(3), None);
+        assert_eq!(re_pattern.group_name(0), None);
+        assert_eq!(re_pattern.group_name(4), None);
+        assert!(match_pattern("name=grep", &mut re_pattern).unwrap());
+        let value = re_pattern.group_index("value").unwrap();
+        assert_eq!(
+            re_pattern.backrefs.as_ref().unwrap()[value - 1]
+                .1
+                .as_deref(),
+            Some("grep")
+        );
+
+        assert_matches(&[
+            (r"(?<word>\w+) \k<word>", "hello hello", true),
+            (r"(?<word>\w+) \k<word>", "hello help", false),
+            (r"(?P<a>x)(?<b>y)\k<b>\k<a>", "xyyx", true),
+            (r"(?i)(?<a>x)\k<a>", "xX", true),
+            (r"(?<_1>a)\1", "aa", true),
+        ]);
+    }
+
+    #[test]
+    fn invalid_group_names() {
+        assert_parse_errors(&[
+            ("(?<1a>x)", ErrorKind::InvalidGroupName, 3..5),
+            ("(?P<a-b>x)", ErrorKind::InvalidGroupName, 4..7),
+            ("(?<>x)", ErrorKind::InvalidGroupName, 3..3),
+            ("(?<abc", ErrorKind::InvalidGroupName, 3..6),
+            ("(?<a)", ErrorKind::InvalidGroupName, 3..5),
+            (
+                "(?<a>x)(?P<a>y)",
+                ErrorKind::DuplicateGroupName("a".to_string()),
+                11..12,
+            ),
+            (
+                r"(?<a>x)\k<b>",
+                ErrorKind::UndefinedGroupName("b".to_string()),
+                7..12,
+            ),
+            (r"\k<a>(?<a>x)", ErrorKind::ForwardBackref, 0..5),
+            (r"\2(a)(b)", ErrorKind::ForwardBackref, 0..2),
+            (r"(a\g{2})(b)", ErrorKind::ForwardBackref, 2..7),
+            (r"\k<b>\3(?<b>x)", ErrorKind::ForwardBackref, 0..5),
+            (r"(?<a>x)\k<a", ErrorKind::InvalidGroupName, 10..11),
+            ("(?P>a)", ErrorKind::UnknownGroupSyntax, 0..3),
+        ]);
+    }
+
+    #[test]
+    fn invalid_flag_groups() {
+        assert_parse_errors(&[
+            ("(?i-q)", ErrorKind::UnknownFlag('q'), 4..5),
+            ("(?i-i-)", ErrorKind::UnknownFlag('-'), 5..6),
+            ("(?z:a)", ErrorKind::UnknownFlag('z'), 2..3),
+            ("(?i:a", ErrorKind::UnclosedGroup, 0..5),
+            ("(?%a)", ErrorKind::UnknownGroupSyntax, 0..3),
+        ]);
+    }
 }
@@ -1,37 +1,134 @@
 use std::env;
-use std::io;
+use std::io::{self, BufRead};
 use std::process;
 
-use codecrafters_grep::{get_regex_pattern, match_pattern};
+use codecrafters_grep::{Flags, ParseError, Property, Regex};
 
-// Usage: echo <input_text> | your_program.sh -E <pattern>
+// Usage: echo <input_text> | your_program.sh [-i] -E <pattern>
 fn main() {
-    if env::args().nth(1).unwrap() != "-E" {
-        println!("Expected first argument to be '-E'");
-        process::exit(1);
+    let mut flags = Flags::default();
+    let mut pattern = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-E" => pattern = args.next(),
+            "-i" => flags.case_insensitive = true,
+            _ => {
+                println!("Unexpected argument '{}'", arg);
+                process::exit(1);
+            }
+        }
     }
+    let Some(pattern) = pattern else {
+        println!("Expected a pattern after '-E'");
+        process::exit(1);
+    };
+    let mut input_line = vec![];
 
-    let pattern = env::args().nth(2).unwrap();
-    let mut input_line = String::new();
-
-    io::stdin().read_line(&mut input_line).unwrap();
+    io::stdin()
+        .lock()
+        .read_until(b'\n', &mut input_line)
+        .unwrap();
+    // like in grep the newline ending the line isn't part of it, `.` doesn't match it
+    if input_line.ends_with(b"\n") {
+        input_line.pop();
+    }
+    // bytes which aren't valid UTF-8 are replaced by U+FFFD instead of rejecting the whole line
+    let input_line = String::from_utf8_lossy(&input_line);
 
-    let mut re_pattern = get_regex_pattern(&pattern);
+    let regex = match Regex::with_flags(&pattern, flags) {
+        Ok(regex) => regex,
+        Err(err) => {
+            eprintln!("{}", diagnostic(&pattern, &err));
+            process::exit(2);
+        }
+    };
     #[cfg(debug_assertions)]
     {
         println!("--------final re pattern--------");
-        for re in re_pattern.rtype.iter() {
+        for re in regex.as_re().rtype.iter() {
             println!("{:?}", re);
         }
         println!("----------------");
-        for backref in re_pattern.backrefs.iter() {
+        for backref in regex.as_re().backrefs.iter() {
             println!("{:?}", backref);
         }
     }
 
-    if match_pattern(&input_line, &mut re_pattern) {
-        process::exit(0);
+    match regex.is_match(&input_line) {
+        Ok(true) => process::exit(0),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+// Renders `err` with a caret line under the offending part of the line of `pattern` it's on,
+// like:
+//
+//   error: missing closing ')' for opened '('
+//     a(bc
+//      ^^^
+//
+// The caret line is padded by display width, tabs are kept so they line up with the pattern's.
+fn diagnostic(pattern: &str, err: &ParseError) -> String {
+    let line_start = pattern[..err.span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = pattern[err.span.start..]
+        .find('\n')
+        .map_or(pattern.len(), |i| err.span.start + i);
+    let padding: String = pattern[line_start..err.span.start]
+        .chars()
+        .map(|c| match c {
+            '\t' => "\t",
+            _ => &"  "[..width(c)],
+        })
+        .collect();
+    let end = err.span.end.min(line_end);
+    let carets = pattern[err.span.start..end]
+        .chars()
+        .map(width)
+        .sum::<usize>()
+        .max(1);
+    format!(
+        "error: {}\n  {}\n  {}{}",
+        err.kind,
+        &pattern[line_start..line_end],
+        padding,
+        "^".repeat(carets)
+    )
+}
+
+// The number of columns `c` takes up in a terminal: 0 for combining marks and format characters,
+// 2 for East Asian wide and fullwidth characters, 1 otherwise.
+fn width(c: char) -> usize {
+    const WIDE: &[(char, char)] = &[
+        ('\u{1100}', '\u{115F}'),
+        ('\u{2E80}', '\u{303E}'),
+        ('\u{3041}', '\u{33FF}'),
+        ('\u{3400}', '\u{4DBF}'),
+        ('\u{4E00}', '\u{9FFF}'),
+        ('\u{A000}', '\u{A4CF}'),
+        ('\u{AC00}', '\u{D7A3}'),
+        ('\u{F900}', '\u{FAFF}'),
+        ('\u{FE30}', '\u{FE4F}'),
+        ('\u{FF00}', '\u{FF60}'),
+        ('\u{FFE0}', '\u{FFE6}'),
+        ('\u{1F300}', '\u{1F64F}'),
+        ('\u{1F900}', '\u{1F9FF}'),
+        ('\u{20000}', '\u{2FFFD}'),
+        ('\u{30000}', '\u{3FFFD}'),
+    ];
+    let zero_width = ["Mn", "Me", "Cf"]
+        .iter()
+        .filter_map(|name| Property::from_name(name))
+        .any(|property| property.matches(c));
+    if zero_width {
+        0
+    } else if WIDE.iter().any(|&(start, end)| (start..=end).contains(&c)) {
+        2
     } else {
-        process::exit(1);
+        1
     }
 }
@@ -0,0 +1,272 @@
+use crate::{Anchor, Quantifier, RType, RE};
+
+// Patterns whose program would be bigger than this (think nested counted repetitions like
+// `(a{1000}){100}`) are left to the backtracker
+const MAX_INSTS: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Inst {
+    Char(RType), // consume a character matched by a single character RType, like Ch or Ccl
+    Split(usize, usize), // continue at both, the first one having priority
+    Jmp(usize),  // continue at the given instruction
+    Save(usize), // record the current position in a capture slot
+    Assert(Anchor), // only continue if the anchor holds at the current position
+    Match,       // the pattern matched
+}
+
+/// Thompson NFA for a pattern, as a program of instructions. The capture slots `2n` and `2n + 1`
+/// hold the start and end of group `n`, group 0 being the whole match.
+#[derive(Debug, Clone)]
+pub(crate) struct Program {
+    pub(crate) insts: Vec<Inst>,
+    pub(crate) slots: usize,
+    pub(crate) anchored: bool,
+}
+
+impl Program {
+    /// Returns `None` if `re` can't be expressed as an NFA, which is the case for backreferences,
+    /// atomic groups and lookarounds, or if the program would be too big.
+    pub(crate) fn compile(re: &RE) -> Option<Program> {
+        let groups = re.backrefs.as_ref().map_or(0, |backrefs| backrefs.len());
+        let mut compiler = Compiler {
+            insts: vec![],
+            nodes: 0,
+        };
+        compiler.push(Inst::Save(0))?;
+        compiler.seq(&re.rtype)?;
+        compiler.push(Inst::Save(1))?;
+        compiler.push(Inst::Match)?;
+        Some(Program {
+            insts: compiler.insts,
+            slots: 2 * (groups + 1),
+            anchored: re.is_anchored_start(),
+        })
+    }
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+    nodes: usize, // nodes compiled so far, bodies without instructions like `(?:)` included
+}
+
+impl Compiler {
+    // Returns the index of the pushed instruction
+    fn push(&mut self, inst: Inst) -> Option<usize> {
+        if self.insts.len() >= MAX_INSTS {
+            return None;
+        }
+        self.insts.push(inst);
+        Some(self.insts.len() - 1)
+    }
+
+    fn next(&self) -> usize {
+        self.insts.len()
+    }
+
+    fn seq(&mut self, rtypes: &[RType]) -> Option<()> {
+        rtypes.iter().try_for_each(|rtype| self.node(rtype))
+    }
+
+    fn node(&mut self, rtype: &RType) -> Option<()> {
+        // charged like an instruction, or repeating an empty body would never reach the limit
+        self.nodes += 1;
+        if self.nodes > MAX_INSTS {
+            return None;
+        }
+        match rtype {
+            RType::Ch(_)
+            | RType::Ccl(_)
+            | RType::Cgd
+            | RType::Cgw
+            | RType::Cgs
+            | RType::Wildcard
+            | RType::AnyChar => {
+                self.push(Inst::Char(rtype.clone()))?;
+            }
+            RType::AltOr(branches) => {
+                // every branch but the last one is preceded by a split to the next branch, and
+                // followed by a jump past the alternation
+                let mut jumps = vec![];
+                let (last, rest) = branches.split_last().expect("alternation has branches");
+                for branch in rest {
+                    let split = self.push(Inst::Split(0, 0))?;
+                    self.seq(branch)?;
+                    jumps.push(self.push(Inst::Jmp(0))?);
+                    self.insts[split] = Inst::Split(split + 1, self.next());
+                }
+                self.seq(last)?;
+                let end = self.next();
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jmp(end);
+                }
+            }
+            RType::Anchor(anchor) => {
+                self.push(Inst::Assert(*anchor))?;
+            }
+            RType::Group(rtypes) => self.seq(rtypes)?,
+            RType::Capture(group, rtypes) => {
+                self.push(Inst::Save(2 * group))?;
+                self.seq(rtypes)?;
+                self.push(Inst::Save(2 * group + 1))?;
+            }
+            RType::Repeat(rtype, quantifier) => self.repeat(rtype, quantifier)?,
+            RType::BackRefs(_)
+            | RType::BackRefsNoCase(_)
+            | RType::Atomic(_)
+            | RType::LookAhead(_)
+            | RType::NegLookAhead(_)
+            | RType::LookBehind(..)
+            | RType::NegLookBehind(..) => return None,
+        }
+        Some(())
+    }
+
+    fn repeat(&mut self, rtype: &RType, quantifier: &Quantifier) -> Option<()> {
+        for _ in 0..quantifier.min {
+            self.node(rtype)?;
+        }
+        let split = |body: usize, skip: usize| {
+            if quantifier.greedy {
+                Inst::Split(body, skip)
+            } else {
+                Inst::Split(skip, body)
+            }
+        };
+        match quantifier.max {
+            None => {
+                // loop: split to the body or past it, the body being followed by another split
+                // back to it. A thread whose iteration matched the empty string finds the body
+                // already visited, and goes past the loop with the captures of that iteration.
+                let start = self.push(Inst::Split(0, 0))?;
+                self.node(rtype)?;
+                let again = self.push(Inst::Split(0, 0))?;
+                self.insts[start] = split(start + 1, self.next());
+                self.insts[again] = split(start + 1, self.next());
+            }
+            Some(max) => {
+                // every optional iteration can skip past all of the remaining ones
+                let mut splits = vec![];
+                for _ in quantifier.min..max {
+                    splits.push(self.push(Inst::Split(0, 0))?);
+                    self.node(rtype)?;
+                }
+                let end = self.next();
+                for start in splits {
+                    self.insts[start] = split(start + 1, end);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+/// Set of instructions which keeps them in insertion order, and is cleared in constant time. It
+/// only grows as far as the instructions inserted, a program which is mostly never run doesn't
+/// take up memory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.dense.clear();
+    }
+
+    pub(crate) fn contains(&self, pc: usize) -> bool {
+        self.index(pc).is_some()
+    }
+
+    /// Position of `pc` in the insertion order, if it's in the set.
+    pub(crate) fn index(&self, pc: usize) -> Option<usize> {
+        let i = *self.sparse.get(pc)?;
+        (i < self.dense.len() && self.dense[i] == pc).then_some(i)
+    }
+
+    /// Returns false if `pc` was already in the set.
+    pub(crate) fn insert(&mut self, pc: usize) -> bool {
+        if self.contains(pc) {
+            return false;
+        }
+        if pc >= self.sparse.len() {
+            self.sparse.resize(pc + 1, 0);
+        }
+        self.sparse[pc] = self.dense.len();
+        self.dense.push(pc);
+        true
+    }
+
+    pub(crate) fn iter(&self) -> std::slice::Iter<'_, usize> {
+        self.dense.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::get_regex_pattern;
+
+    fn compile(pattern: &str) -> Option<Program> {
+        Program::compile(&get_regex_pattern(pattern).unwrap())
+    }
+
+    #[test]
+    fn compile_alternation_and_star() {
+        let program = compile("(a|b)*").unwrap();
+        assert_eq!(
+            program.insts,
+            vec![
+                Inst::Save(0),
+                Inst::Split(2, 9),
+                Inst::Save(2),
+                Inst::Split(4, 6),
+                Inst::Char(RType::Ch('a')),
+                Inst::Jmp(7),
+                Inst::Char(RType::Ch('b')),
+                Inst::Save(3),
+                Inst::Split(2, 9),
+                Inst::Save(1),
+                Inst::Match,
+            ]
+        );
+        assert_eq!(program.slots, 4);
+    }
+
+    #[test]
+    fn compile_lazy_counted_repetition() {
+        let program = compile("^a{1,2}?$").unwrap();
+        assert_eq!(
+            program.insts,
+            vec![
+                Inst::Save(0),
+                Inst::Assert(Anchor::Start),
+                Inst::Char(RType::Ch('a')),
+                Inst::Split(5, 4),
+                Inst::Char(RType::Ch('a')),
+                Inst::Assert(Anchor::End),
+                Inst::Save(1),
+                Inst::Match,
+            ]
+        );
+        assert!(program.anchored);
+        assert!(!compile("(^a)|b").unwrap().anchored);
+    }
+
+    #[test]
+    fn backtracking_only_patterns() {
+        assert!(compile(r"(a)\1").is_none());
+        assert!(compile("(?>a+)b").is_none());
+        assert!(compile("a*+b").is_none());
+        assert!(compile("a(?=b)").is_none());
+        assert!(compile("(?<!a)b").is_none());
+        assert!(compile("(a{100}){100}").is_some());
+        assert!(compile("(a{1000}){100}").is_none());
+        assert!(compile("((?:){100}){100}").is_some());
+        assert!(compile("((?:){1000}){1000}").is_none());
+    }
+}
@@ -0,0 +1,1155 @@
+use std::ops::Range;
+use std::slice;
+
+use crate::class::{CharClass, ClassItem, Posix};
+use crate::unicode::other_cases;
+use crate::{Anchor, ErrorKind, Flags, ParseError, Property, Quantifier, RType, RE};
+
+// Grammar handled by the parser:
+//
+//   pattern     := alternation
+//   alternation := concat ('|' concat)*
+//   concat      := (repeat | '(?' flags ')')*
+//   repeat      := atom (('+' | '?' | '*' | '{' n (',' m?)? '}') ('?' | '+')?)?
+//   atom        := '.' | '^' | '$' | '(' group? alternation ')' | '[' class ']' | '\' escape | char
+//   group       := '?>' | '?' flags ':' | '?' ('=' | '!' | '<=' | '<!') | '?' 'P'? '<' name '>'
+//   flags       := [imsux]* ('-' [imsux]*)?
+//
+// In verbose mode (`x` flag) whitespace and `#` comments are skipped between the repeats.
+//
+// Every character of the pattern is looked at once, so parsing is linear in the pattern length.
+
+// Largest count of a `{n,m}` repetition, like in other engines
+const MAX_REPETITION: u32 = 1000;
+
+// Deepest nesting of groups, the parser (and the engines walking the parsed RTypes) recursing
+// into every group
+const MAX_NESTING: usize = 250;
+
+pub(crate) struct Parser<'p> {
+    pattern: &'p str,
+    pos: usize, // byte offset of the next character to parse
+    flags: Flags,
+    depth: usize, // groups opened around the current position
+    groups: Vec<(Range<usize>, Option<String>)>,
+    group_names: Vec<Option<String>>,
+    // back-references to groups which weren't opened yet, by number and by name
+    backref_sites: Vec<(usize, Range<usize>)>,
+    name_sites: Vec<(String, Range<usize>)>,
+}
+
+impl<'p> Parser<'p> {
+    pub(crate) fn new(pattern: &'p str, flags: Flags) -> Self {
+        Parser {
+            pattern,
+            pos: 0,
+            flags,
+            depth: 0,
+            groups: vec![],
+            group_names: vec![],
+            backref_sites: vec![],
+            name_sites: vec![],
+        }
+    }
+
+    pub(crate) fn parse(mut self) -> Result<RE, ParseError> {
+        let rtype = self.parse_alternation()?;
+        if let Some(')') = self.peek() {
+            return Err(ParseError::new(
+                ErrorKind::UnopenedGroup,
+                self.pos..self.pos + 1,
+            ));
+        }
+
+        // a group only defined after the back-reference to it can't have captured anything yet
+        let numbered = self.backref_sites.iter().map(|(bnum, span)| {
+            let kind = if *bnum <= self.groups.len() {
+                ErrorKind::ForwardBackref
+            } else {
+                ErrorKind::UndefinedBackref(*bnum)
+            };
+            ParseError::new(kind, span.clone())
+        });
+        let named = self.name_sites.iter().map(|(name, span)| {
+            let kind = if self.group_names.iter().flatten().any(|other| other == name) {
+                ErrorKind::ForwardBackref
+            } else {
+                ErrorKind::UndefinedGroupName(name.clone())
+            };
+            ParseError::new(kind, span.clone())
+        });
+        if let Some(err) = numbered.chain(named).min_by_key(|err| err.span.start) {
+            return Err(err);
+        }
+
+        Ok(RE {
+            rtype,
+            backrefs: Some(self.groups),
+            group_names: self.group_names,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.pattern[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    // Stops in front of an unmatched ')' or at the end of the pattern, it's up to the caller to
+    // decide whether that's valid. A single branch is returned as is, without an `AltOr` around it.
+    fn parse_alternation(&mut self) -> Result<Vec<RType>, ParseError> {
+        let mut branches = vec![self.parse_concat()?];
+        while let Some('|') = self.peek() {
+            self.bump();
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            return Ok(branches.remove(0));
+        }
+        Ok(vec![RType::AltOr(branches)])
+    }
+
+    fn parse_concat(&mut self) -> Result<Vec<RType>, ParseError> {
+        let mut rtypes: Vec<RType> = vec![];
+        // whether there's an RType right before for a quantifier to apply to, and whether it's
+        // already quantified
+        let mut can_repeat = false;
+        let mut quantified = false;
+        loop {
+            self.skip_ignored();
+            let Some(c) = self.peek() else {
+                break;
+            };
+            match c {
+                '|' | ')' => break,
+                '(' if self.parse_flag_group()? => {
+                    can_repeat = false;
+                    continue;
+                }
+                '+' | '?' | '*' if can_repeat => {
+                    let start = self.pos;
+                    self.bump();
+                    let quantifier = match c {
+                        '+' => Quantifier::PLUS,
+                        '?' => Quantifier::QUESTION,
+                        _ => Quantifier::STAR,
+                    };
+                    self.repeat(&mut rtypes, quantifier, start, quantified)?;
+                    quantified = true;
+                }
+                '{' if can_repeat => {
+                    let start = self.pos;
+                    match self.parse_counted_repetition()? {
+                        Some(quantifier) => {
+                            self.repeat(&mut rtypes, quantifier, start, quantified)?;
+                            quantified = true;
+                        }
+                        // not a repetition, so it's taken literally
+                        None => {
+                            rtypes.push(self.parse_atom()?);
+                            quantified = false;
+                        }
+                    }
+                }
+                _ => {
+                    rtypes.push(self.parse_atom()?);
+                    quantified = false;
+                }
+            }
+            can_repeat = true;
+        }
+        Ok(rtypes)
+    }
+
+    // Applies `quantifier` (which started at `start`) to the last RType of the sequence. It's made
+    // lazy if followed by a '?', and possessive (an atomic group around the repetition) if
+    // followed by a '+'.
+    fn repeat(
+        &mut self,
+        rtypes: &mut Vec<RType>,
+        mut quantifier: Quantifier,
+        start: usize,
+        quantified: bool,
+    ) -> Result<(), ParseError> {
+        let mut possessive = false;
+        match self.peek() {
+            Some('?') => {
+                self.bump();
+                quantifier = quantifier.lazy();
+            }
+            Some('+') => {
+                self.bump();
+                possessive = true;
+            }
+            _ => {}
+        }
+        if quantified {
+            return Err(ParseError::new(
+                ErrorKind::RepeatedQuantifier,
+                start..self.pos,
+            ));
+        }
+        let last = rtypes.pop().expect("sequence shouldn't be empty");
+        let rtype = RType::Repeat(Box::new(last), quantifier);
+        rtypes.push(if possessive {
+            RType::Atomic(Box::new(vec![rtype]))
+        } else {
+            rtype
+        });
+        Ok(())
+    }
+
+    // Parses `{n}`, `{n,}` or `{n,m}`. Returns `None` without consuming anything when the braces
+    // don't form a repetition, e.g. `{`, `{a}` or `{,2}`.
+    fn parse_counted_repetition(&mut self) -> Result<Option<Quantifier>, ParseError> {
+        let start = self.pos;
+        let rest = &self.pattern[start + 1..];
+        let Some(close) = rest.find('}') else {
+            return Ok(None);
+        };
+        let (min, max) = match rest[..close].split_once(',') {
+            Some((min, max)) => (min, Some(max)),
+            None => (&rest[..close], None),
+        };
+        let is_count = |count: &str| !count.is_empty() && count.chars().all(|c| c.is_ascii_digit());
+        if !is_count(min) || max.is_some_and(|max| !max.is_empty() && !is_count(max)) {
+            return Ok(None);
+        }
+        let span = start..start + close + 2;
+        let count = |count: &str| {
+            count
+                .parse::<u32>()
+                .ok()
+                .filter(|&count| count <= MAX_REPETITION)
+                .ok_or_else(|| ParseError::new(ErrorKind::RepetitionTooLarge, span.clone()))
+        };
+        let quantifier = Quantifier::new(
+            count(min)?,
+            match max {
+                None => Some(count(min)?),
+                Some("") => None,
+                Some(max) => Some(count(max)?),
+            },
+        );
+        if quantifier.max.is_some_and(|max| max < quantifier.min) {
+            return Err(ParseError::new(ErrorKind::InvalidRepetitionRange, span));
+        }
+        self.pos = span.end;
+        Ok(Some(quantifier))
+    }
+
+    fn parse_atom(&mut self) -> Result<RType, ParseError> {
+        let start = self.pos;
+        match self
+            .bump()
+            .expect("caller should have checked for end of pattern")
+        {
+            '.' if self.flags.dot_matches_new_line => Ok(RType::AnyChar),
+            '.' => Ok(RType::Wildcard),
+            '^' if self.flags.multi_line => Ok(RType::Anchor(Anchor::LineStart)),
+            '^' => Ok(RType::Anchor(Anchor::Start)),
+            '$' if self.flags.multi_line => Ok(RType::Anchor(Anchor::LineEnd)),
+            '$' => Ok(RType::Anchor(Anchor::End)),
+            '(' => {
+                if self.depth == MAX_NESTING {
+                    return Err(ParseError::new(ErrorKind::NestingTooDeep, start..start + 1));
+                }
+                self.depth += 1;
+                let rtype = self.parse_group(start);
+                self.depth -= 1;
+                rtype
+            }
+            '[' => self.parse_class(start),
+            '\\' => self.parse_escape(start),
+            c => Ok(self.literal(c)),
+        }
+    }
+
+    // A literal character, which matches its other cases as well when ignoring case
+    fn literal(&self, c: char) -> RType {
+        let others = other_cases(c);
+        if !self.flags.case_insensitive || others.is_empty() {
+            return RType::Ch(c);
+        }
+        let mut items = vec![ClassItem::Ch(c)];
+        items.extend(others.iter().map(|other| ClassItem::Ch(*other)));
+        RType::Ccl(CharClass {
+            items,
+            negated: false,
+        })
+    }
+
+    fn parse_group(&mut self, start: usize) -> Result<RType, ParseError> {
+        let name = match self.parse_group_name()? {
+            Some(name) => Some(name),
+            None if self.peek() == Some('?') => return self.parse_special_group(start),
+            None => None,
+        };
+        // reserve the slot now, so that groups are numbered by their opening parenthesis
+        let slot = self.groups.len();
+        self.groups.push((start..start, None));
+        self.group_names.push(name);
+        let rtype = self.parse_scoped_alternation()?;
+        if self.bump() != Some(')') {
+            return Err(ParseError::new(
+                ErrorKind::UnclosedGroup,
+                start..self.pattern.len(),
+            ));
+        }
+        self.groups[slot].0.end = self.pos;
+        Ok(RType::Capture(slot + 1, Box::new(rtype)))
+    }
+
+    // Parses the `?P<name>` or `?<name>` starting a named group. Returns `None` without consuming
+    // anything if the group at the current position isn't named.
+    fn parse_group_name(&mut self) -> Result<Option<String>, ParseError> {
+        let rest = &self.pattern[self.pos..];
+        let Some(rest) = rest.strip_prefix("?P<").or_else(|| rest.strip_prefix("?<")) else {
+            return Ok(None);
+        };
+        // lookbehinds start like named groups
+        if rest.starts_with(['=', '!']) {
+            return Ok(None);
+        }
+        let name_start = self.pattern.len() - rest.len();
+        let name = self.parse_name(name_start, '>')?;
+        if self
+            .group_names
+            .iter()
+            .flatten()
+            .any(|other| *other == name)
+        {
+            return Err(ParseError::new(
+                ErrorKind::DuplicateGroupName(name),
+                name_start..self.pos - 1,
+            ));
+        }
+        Ok(Some(name))
+    }
+
+    // Parses a group name starting at `start` and ending with `close`, moving past the `close`
+    fn parse_name(&mut self, start: usize, close: char) -> Result<String, ParseError> {
+        let rest = &self.pattern[start..];
+        let Some(end) = rest.find(close) else {
+            return Err(ParseError::new(
+                ErrorKind::InvalidGroupName,
+                start..self.pattern.len(),
+            ));
+        };
+        let name = &rest[..end];
+        let is_valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_valid {
+            return Err(ParseError::new(
+                ErrorKind::InvalidGroupName,
+                start..start + end,
+            ));
+        }
+        self.pos = start + end + close.len_utf8();
+        Ok(name.to_string())
+    }
+
+    // Groups starting with `(?`, which don't capture anything
+    fn parse_special_group(&mut self, start: usize) -> Result<RType, ParseError> {
+        self.bump();
+        let rest = &self.pattern[self.pos..];
+        let rtype = if let Some(kind) = ["=", "!", "<=", "<!"]
+            .into_iter()
+            .find(|kind| rest.starts_with(kind))
+        {
+            self.pos += kind.len();
+            let rtypes = Box::new(self.parse_scoped_alternation()?);
+            match kind {
+                "=" => RType::LookAhead(rtypes),
+                "!" => RType::NegLookAhead(rtypes),
+                "<=" => {
+                    let (min_len, max_len) = self.lookbehind_len(&rtypes, start)?;
+                    RType::LookBehind(min_len, max_len, rtypes)
+                }
+                _ => {
+                    let (min_len, max_len) = self.lookbehind_len(&rtypes, start)?;
+                    RType::NegLookBehind(min_len, max_len, rtypes)
+                }
+            }
+        } else if self.peek() == Some('>') {
+            self.bump();
+            RType::Atomic(Box::new(self.parse_scoped_alternation()?))
+        } else {
+            // `(?:...)`, with flags only applying inside of the group like in `(?i:...)`
+            let rest = &self.pattern[self.pos..];
+            let letters = flag_letters(rest);
+            if !rest[letters.len()..].starts_with(':') {
+                self.bump();
+                return Err(ParseError::new(
+                    ErrorKind::UnknownGroupSyntax,
+                    start..self.pos,
+                ));
+            }
+            let flags = self.parse_flags(letters, self.pos)?;
+            self.pos += letters.len() + 1;
+            let outer = std::mem::replace(&mut self.flags, flags);
+            let rtypes = self.parse_alternation();
+            self.flags = outer;
+            RType::Group(Box::new(rtypes?))
+        };
+        if self.bump() != Some(')') {
+            return Err(ParseError::new(
+                ErrorKind::UnclosedGroup,
+                start..self.pattern.len(),
+            ));
+        }
+        Ok(rtype)
+    }
+
+    // Shortest and longest number of characters matched by the lookbehind starting at `start`,
+    // whose group `rtypes` was just parsed. Only bounded lookbehinds are supported, they're matched
+    // from every start they allow before the current position.
+    fn lookbehind_len(&self, rtypes: &[RType], start: usize) -> Result<(usize, usize), ParseError> {
+        if self.peek() != Some(')') {
+            return Err(ParseError::new(
+                ErrorKind::UnclosedGroup,
+                start..self.pattern.len(),
+            ));
+        }
+        len_bounds(rtypes)
+            .ok_or_else(|| ParseError::new(ErrorKind::UnboundedLookBehind, start..self.pos + 1))
+    }
+
+    // Alternation inside of a group, flags set inside of it don't apply after the group
+    fn parse_scoped_alternation(&mut self) -> Result<Vec<RType>, ParseError> {
+        let flags = self.flags;
+        let rtype = self.parse_alternation();
+        self.flags = flags;
+        rtype
+    }
+
+    // Parses a group only setting flags like `(?i)` or `(?-s)`, they apply to the rest of the
+    // enclosing group (or pattern). Returns false without consuming anything if the group at the
+    // current position isn't one.
+    fn parse_flag_group(&mut self) -> Result<bool, ParseError> {
+        let start = self.pos;
+        let Some(rest) = self.pattern[start..].strip_prefix("(?") else {
+            return Ok(false);
+        };
+        let letters = flag_letters(rest);
+        if letters.is_empty() || !rest[letters.len()..].starts_with(')') {
+            return Ok(false);
+        }
+        self.flags = self.parse_flags(letters, start + 2)?;
+        self.pos = start + 2 + letters.len() + 1;
+        Ok(true)
+    }
+
+    // Returns the current flags changed by `letters` like `im-sx`, which start at offset `start`:
+    // the flags before the '-' are enabled, the ones after it disabled
+    fn parse_flags(&self, letters: &str, start: usize) -> Result<Flags, ParseError> {
+        let mut flags = self.flags;
+        let mut enable = true;
+        for (i, letter) in letters.char_indices() {
+            let flag = match letter {
+                '-' if enable => {
+                    enable = false;
+                    continue;
+                }
+                'i' => &mut flags.case_insensitive,
+                'm' => &mut flags.multi_line,
+                's' => &mut flags.dot_matches_new_line,
+                'u' => &mut flags.unicode,
+                'x' => &mut flags.ignore_whitespace,
+                _ => {
+                    return Err(ParseError::new(
+                        ErrorKind::UnknownFlag(letter),
+                        start + i..start + i + 1,
+                    ))
+                }
+            };
+            *flag = enable;
+        }
+        Ok(flags)
+    }
+
+    // Skips the whitespace and `#` comments (up to the end of the line) of the pattern in verbose
+    // mode
+    fn skip_ignored(&mut self) {
+        if !self.flags.ignore_whitespace {
+            return;
+        }
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => {
+                    let rest = &self.pattern[self.pos..];
+                    self.pos += rest.find('\n').map_or(rest.len(), |newline| newline + 1);
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<RType, ParseError> {
+        let mut negated = false;
+        if let Some('^') = self.peek() {
+            self.bump();
+            negated = true;
+        }
+        let mut items = vec![];
+        // a ']' right after the opening bracket doesn't close the class
+        if let Some(']') = self.peek() {
+            self.bump();
+            items.push(ClassItem::Ch(']'));
+        }
+        loop {
+            let item_start = self.pos;
+            let item = match self.bump() {
+                Some(']') => break,
+                Some('[') if self.peek() == Some(':') => self.parse_posix_class(item_start)?,
+                Some('\\') => self.parse_class_escape(item_start)?,
+                Some(c) => ClassItem::Ch(c),
+                None => {
+                    return Err(ParseError::new(ErrorKind::UnclosedClass, start..self.pos));
+                }
+            };
+            // a '-' makes a range, unless it's the last character of the class
+            let is_range = self.peek() == Some('-')
+                && !self.pattern[self.pos + 1..].starts_with(']')
+                && self.pos + 1 < self.pattern.len();
+            match item {
+                ClassItem::Ch(first) if is_range => {
+                    self.bump();
+                    let end_start = self.pos;
+                    let last = match self.bump() {
+                        Some('\\') => self.parse_class_escape(end_start)?,
+                        Some(c) => ClassItem::Ch(c),
+                        None => unreachable!("checked that the range has an end"),
+                    };
+                    match last {
+                        ClassItem::Ch(last) if first <= last => {
+                            items.push(ClassItem::Range(first, last));
+                        }
+                        _ => {
+                            return Err(ParseError::new(
+                                ErrorKind::InvalidClassRange,
+                                item_start..self.pos,
+                            ));
+                        }
+                    }
+                }
+                item => items.push(item),
+            }
+        }
+        let mut class = CharClass { items, negated };
+        if self.flags.case_insensitive {
+            class.add_other_cases();
+        }
+        Ok(RType::Ccl(class))
+    }
+
+    // Parses `[:name:]` or `[:^name:]` right after the '['. If what follows doesn't look like a
+    // POSIX class, the '[' is taken literally.
+    fn parse_posix_class(&mut self, start: usize) -> Result<ClassItem, ParseError> {
+        let rest = &self.pattern[self.pos + 1..];
+        let Some(close) = rest.find(":]") else {
+            return Ok(ClassItem::Ch('['));
+        };
+        let (negated, name) = match rest[..close].strip_prefix('^') {
+            Some(name) => (true, name),
+            None => (false, &rest[..close]),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Ok(ClassItem::Ch('['));
+        }
+        self.pos += close + 3;
+        let Some(posix) = Posix::from_name(name) else {
+            return Err(ParseError::new(
+                ErrorKind::UnknownPosixClass(name.to_string()),
+                start..self.pos,
+            ));
+        };
+        let item = ClassItem::Posix(posix);
+        Ok(if negated {
+            ClassItem::Negated(Box::new(item))
+        } else {
+            item
+        })
+    }
+
+    // Escapes inside a bracket expression, `start` is the offset of the '\'
+    fn parse_class_escape(&mut self, start: usize) -> Result<ClassItem, ParseError> {
+        let Some(c) = self.bump() else {
+            return Err(ParseError::new(
+                ErrorKind::TrailingBackslash,
+                start..self.pos,
+            ));
+        };
+        if let Some(c) = self.parse_char_escape(c, start)? {
+            return Ok(ClassItem::Ch(c));
+        }
+        self.parse_class_item_escape(c, start)?
+            .ok_or_else(|| ParseError::new(ErrorKind::UnknownEscape(c), start..self.pos))
+    }
+
+    // Escapes standing for a class of characters, like `\d` or `\p{Greek}`, which mean the same
+    // inside and outside of bracket expressions
+    fn parse_class_item_escape(
+        &mut self,
+        c: char,
+        start: usize,
+    ) -> Result<Option<ClassItem>, ParseError> {
+        let unicode = self.flags.unicode;
+        let perl = |ascii, property| {
+            if unicode {
+                ClassItem::Property(property)
+            } else {
+                ascii
+            }
+        };
+        Ok(Some(match c {
+            'd' => perl(ClassItem::Digit, Property::PERL_DIGIT),
+            'w' => perl(ClassItem::Word, Property::PERL_WORD),
+            's' => perl(ClassItem::Space, Property::PERL_SPACE),
+            'D' => ClassItem::Negated(Box::new(perl(ClassItem::Digit, Property::PERL_DIGIT))),
+            'W' => ClassItem::Negated(Box::new(perl(ClassItem::Word, Property::PERL_WORD))),
+            'S' => ClassItem::Negated(Box::new(perl(ClassItem::Space, Property::PERL_SPACE))),
+            'p' => ClassItem::Property(self.parse_property(start)?),
+            'P' => ClassItem::Negated(Box::new(ClassItem::Property(self.parse_property(start)?))),
+            _ => return Ok(None),
+        }))
+    }
+
+    // Parses the name of the property after `\p` or `\P`, a single letter like in `\pL` or a name
+    // between braces like in `\p{Greek}`
+    fn parse_property(&mut self, start: usize) -> Result<Property, ParseError> {
+        let pattern = self.pattern;
+        let name = match self.bump() {
+            Some('{') => {
+                let Some(close) = pattern[self.pos..].find('}') else {
+                    return Err(ParseError::new(
+                        ErrorKind::InvalidPropertySyntax,
+                        start..pattern.len(),
+                    ));
+                };
+                let name = &pattern[self.pos..self.pos + close];
+                self.pos += close + 1;
+                name
+            }
+            Some(c) if c.is_ascii_alphabetic() => &pattern[self.pos - 1..self.pos],
+            _ => {
+                return Err(ParseError::new(
+                    ErrorKind::InvalidPropertySyntax,
+                    start..self.pos,
+                ))
+            }
+        };
+        Property::from_name(name).ok_or_else(|| {
+            ParseError::new(
+                ErrorKind::UnknownProperty(name.to_string()),
+                start..self.pos,
+            )
+        })
+    }
+
+    fn parse_escape(&mut self, start: usize) -> Result<RType, ParseError> {
+        let Some(c) = self.bump() else {
+            return Err(ParseError::new(
+                ErrorKind::TrailingBackslash,
+                start..self.pos,
+            ));
+        };
+        if let Some(c) = self.parse_char_escape(c, start)? {
+            return Ok(self.literal(c));
+        }
+        match self.parse_class_item_escape(c, start)? {
+            Some(ClassItem::Digit) => return Ok(RType::Cgd),
+            Some(ClassItem::Word) => return Ok(RType::Cgw),
+            Some(ClassItem::Space) => return Ok(RType::Cgs),
+            Some(item) => {
+                let mut class = match item {
+                    ClassItem::Negated(item) => CharClass {
+                        items: vec![*item],
+                        negated: true,
+                    },
+                    item => CharClass {
+                        items: vec![item],
+                        negated: false,
+                    },
+                };
+                if self.flags.case_insensitive {
+                    class.add_other_cases();
+                }
+                return Ok(RType::Ccl(class));
+            }
+            None => {}
+        }
+        match c {
+            'k' if self.peek() == Some('<') => {
+                let name = self.parse_name(self.pos + 1, '>')?;
+                match self
+                    .group_names
+                    .iter()
+                    .position(|other| other.as_deref() == Some(name.as_str()))
+                {
+                    Some(slot) => Ok(self.backref(slot + 1)),
+                    None => {
+                        // the error is reported once the whole pattern is parsed
+                        self.name_sites.push((name, start..self.pos));
+                        Ok(self.backref(0))
+                    }
+                }
+            }
+            'A' => Ok(RType::Anchor(Anchor::Start)),
+            'z' => Ok(RType::Anchor(Anchor::End)),
+            'Z' => Ok(RType::Anchor(Anchor::EndBeforeNewline)),
+            'b' if self.flags.unicode => Ok(RType::Anchor(Anchor::UnicodeWordBoundary)),
+            'b' => Ok(RType::Anchor(Anchor::WordBoundary)),
+            'B' if self.flags.unicode => Ok(RType::Anchor(Anchor::UnicodeNotWordBoundary)),
+            'B' => Ok(RType::Anchor(Anchor::NotWordBoundary)),
+            '0' => Err(ParseError::new(ErrorKind::ZeroBackref, start..self.pos)),
+            '1'..='9' => {
+                // every digit that follows is part of the number, \g{1}0 is the way to have group 1
+                // followed by a 0
+                let digits = &self.pattern[start + 1..];
+                let len = digits
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(digits.len());
+                self.pos = start + 1 + len;
+                let bnum = group_number(&digits[..len]);
+                if bnum > self.groups.len() {
+                    self.backref_sites.push((bnum, start..self.pos));
+                }
+                Ok(self.backref(bnum))
+            }
+            'g' => self.parse_braced_backref(start),
+            c => Err(ParseError::new(
+                ErrorKind::UnknownEscape(c),
+                start..self.pos,
+            )),
+        }
+    }
+
+    // Parses the `{N}` or `{-N}` after a `\g` at `start`, `{-N}` referring to the Nth group opened
+    // before it
+    fn parse_braced_backref(&mut self, start: usize) -> Result<RType, ParseError> {
+        let rest = &self.pattern[self.pos..];
+        let Some(number) = rest
+            .strip_prefix('{')
+            .and_then(|rest| Some(&rest[..rest.find('}')?]))
+        else {
+            return Err(ParseError::new(
+                ErrorKind::InvalidBackrefSyntax,
+                start..self.pos,
+            ));
+        };
+        self.pos += number.len() + 2;
+        let span = start..self.pos;
+        let (relative, digits) = match number.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, number),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(ParseError::new(ErrorKind::InvalidBackrefSyntax, span));
+        }
+        let bnum = group_number(digits);
+        if bnum == 0 {
+            return Err(ParseError::new(ErrorKind::ZeroBackref, span));
+        }
+        if !relative {
+            if bnum > self.groups.len() {
+                self.backref_sites.push((bnum, span));
+            }
+            return Ok(self.backref(bnum));
+        }
+        match self.groups.len().checked_sub(bnum) {
+            Some(slot) => Ok(self.backref(slot + 1)),
+            None => Err(ParseError::new(
+                ErrorKind::UndefinedRelativeBackref(bnum),
+                span,
+            )),
+        }
+    }
+
+    fn backref(&self, bnum: usize) -> RType {
+        if self.flags.case_insensitive {
+            return RType::BackRefsNoCase(bnum);
+        }
+        RType::BackRefs(bnum)
+    }
+
+    // Escapes standing for a single character, which mean the same inside and outside of bracket
+    // expressions. `c` is the character right after the '\' at `start`, `None` is returned if it
+    // doesn't start one of them.
+    fn parse_char_escape(&mut self, c: char, start: usize) -> Result<Option<char>, ParseError> {
+        Ok(Some(match c {
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            'f' => '\x0C',
+            'v' => '\x0B',
+            'a' => '\x07',
+            'e' => '\x1B',
+            'x' if self.peek() == Some('{') => self.parse_hex_escape(start, None)?,
+            'x' => self.parse_hex_escape(start, Some(2))?,
+            'u' if self.peek() == Some('{') => self.parse_hex_escape(start, None)?,
+            'u' => self.parse_hex_escape(start, Some(4))?,
+            // escaped metacharacters (and any other punctuation) are taken literally
+            c if !c.is_alphanumeric() => c,
+            _ => return Ok(None),
+        }))
+    }
+
+    // Parses the digits of `\xHH`/`\uHHHH` (exactly `len` digits) or of `\x{...}`/`\u{...}` (when
+    // `len` is `None`)
+    fn parse_hex_escape(&mut self, start: usize, len: Option<usize>) -> Result<char, ParseError> {
+        let rest = &self.pattern[self.pos..];
+        let (digits, consumed) = match len {
+            Some(len) => {
+                let digits = match rest.char_indices().nth(len) {
+                    Some((offset, _)) => &rest[..offset],
+                    None => rest,
+                };
+                (digits, digits.len())
+            }
+            None => match rest.find('}') {
+                Some(close) => (&rest[1..close], close + 1),
+                None => {
+                    return Err(ParseError::new(
+                        ErrorKind::InvalidHexEscape,
+                        start..self.pattern.len(),
+                    ))
+                }
+            },
+        };
+        let end = self.pos + consumed;
+        let is_hex = |digits: &str| {
+            (1..=8).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_hexdigit())
+        };
+        if !is_hex(digits) || len.is_some_and(|len| digits.chars().count() != len) {
+            return Err(ParseError::new(ErrorKind::InvalidHexEscape, start..end));
+        }
+        self.pos = end;
+        let codepoint = u32::from_str_radix(digits, 16).expect("digits should be hexadecimal");
+        char::from_u32(codepoint)
+            .ok_or_else(|| ParseError::new(ErrorKind::InvalidCodepoint(codepoint), start..self.pos))
+    }
+}
+
+// Group number made of ASCII `digits`, numbers too large to fit can't be a group anyway
+fn group_number(digits: &str) -> usize {
+    digits.parse().unwrap_or(usize::MAX)
+}
+
+// Shortest and longest number of characters `rtypes` can match, `None` if there's no longest or
+// it depends on what was captured, like with a backreference
+fn len_bounds(rtypes: &[RType]) -> Option<(usize, usize)> {
+    rtypes
+        .iter()
+        .try_fold((0usize, 0usize), |(min, max), rtype| {
+            let (rtype_min, rtype_max) = match rtype {
+                RType::Ch(_)
+                | RType::Ccl(_)
+                | RType::Cgd
+                | RType::Cgw
+                | RType::Cgs
+                | RType::Wildcard
+                | RType::AnyChar => (1, 1),
+                RType::Repeat(rtype, quantifier) => {
+                    let (rtype_min, rtype_max) = len_bounds(slice::from_ref(rtype))?;
+                    (
+                        rtype_min.saturating_mul(quantifier.min as usize),
+                        rtype_max.checked_mul(quantifier.max? as usize)?,
+                    )
+                }
+                RType::AltOr(branches) => branches
+                    .iter()
+                    .map(|branch| len_bounds(branch))
+                    .try_fold((usize::MAX, 0), |(min, max), bounds| {
+                        let (branch_min, branch_max) = bounds?;
+                        Some((min.min(branch_min), max.max(branch_max)))
+                    })?,
+                RType::Capture(_, rtypes) | RType::Group(rtypes) | RType::Atomic(rtypes) => {
+                    len_bounds(rtypes)?
+                }
+                RType::BackRefs(_) | RType::BackRefsNoCase(_) => return None,
+                RType::Anchor(_)
+                | RType::LookAhead(_)
+                | RType::NegLookAhead(_)
+                | RType::LookBehind(..)
+                | RType::NegLookBehind(..) => (0, 0),
+            };
+            Some((min.saturating_add(rtype_min), max.checked_add(rtype_max)?))
+        })
+}
+
+// Letters of the flags at the start of `rest`, like `im-s` for `im-s:...)`
+fn flag_letters(rest: &str) -> &str {
+    let end = rest
+        .find(|c: char| !c.is_ascii_alphabetic() && c != '-')
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(pattern: &str) -> RE {
+        Parser::new(pattern, Flags::default()).parse().unwrap()
+    }
+
+    #[test]
+    fn nested_groups() {
+        let re = parse("((a)b)");
+        let inner = RType::Capture(2, Box::new(vec![RType::Ch('a')]));
+        assert_eq!(
+            re.rtype,
+            vec![RType::Capture(
+                1,
+                Box::new(vec![inner.clone(), RType::Ch('b')])
+            )]
+        );
+        assert_eq!(re.backrefs, Some(vec![(0..6, None), (1..4, None)]));
+    }
+
+    #[test]
+    fn alternation_inside_nested_group() {
+        let re = parse("(a(b|c))d");
+        let alt = RType::AltOr(vec![vec![RType::Ch('b')], vec![RType::Ch('c')]]);
+        assert_eq!(
+            re.rtype,
+            vec![
+                RType::Capture(
+                    1,
+                    Box::new(vec![
+                        RType::Ch('a'),
+                        RType::Capture(2, Box::new(vec![alt.clone()])),
+                    ])
+                ),
+                RType::Ch('d'),
+            ]
+        );
+        assert_eq!(re.backrefs.unwrap()[1], (2..7, None));
+    }
+
+    #[test]
+    fn sibling_alternations_stay_in_their_group() {
+        let re = parse("(a|b)(c|d)");
+        assert_eq!(
+            re.rtype,
+            vec![
+                RType::Capture(
+                    1,
+                    Box::new(vec![RType::AltOr(vec![
+                        vec![RType::Ch('a')],
+                        vec![RType::Ch('b')],
+                    ])])
+                ),
+                RType::Capture(
+                    2,
+                    Box::new(vec![RType::AltOr(vec![
+                        vec![RType::Ch('c')],
+                        vec![RType::Ch('d')],
+                    ])])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn n_way_and_top_level_alternation() {
+        let re = parse("cat|dog|bird");
+        let word = |w: &str| w.chars().map(RType::Ch).collect::<Vec<_>>();
+        assert_eq!(
+            re.rtype,
+            vec![RType::AltOr(vec![word("cat"), word("dog"), word("bird")])]
+        );
+        assert_eq!(re.backrefs, Some(vec![]));
+
+        let re = parse("(a|)");
+        let alt = vec![RType::AltOr(vec![word("a"), vec![]])];
+        assert_eq!(re.rtype, vec![RType::Capture(1, Box::new(alt.clone()))]);
+        assert_eq!(re.backrefs, Some(vec![(0..4, None)]));
+    }
+
+    #[test]
+    fn deeply_nested_groups_with_quantifiers() {
+        let re = parse("((((x)+)?)+)");
+        let mut expected = RType::Ch('x');
+        for (group, quantify) in [
+            (4, Quantifier::PLUS),
+            (3, Quantifier::QUESTION),
+            (2, Quantifier::PLUS),
+        ] {
+            expected = RType::Repeat(
+                Box::new(RType::Capture(group, Box::new(vec![expected]))),
+                quantify,
+            );
+        }
+        assert_eq!(re.rtype, vec![RType::Capture(1, Box::new(vec![expected]))]);
+        assert_eq!(re.backrefs.unwrap().len(), 4);
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        assert_eq!(
+            Parser::new("(a(b)", Flags::default()).parse(),
+            Err(ParseError::new(ErrorKind::UnclosedGroup, 0..5))
+        );
+        assert_eq!(
+            Parser::new("a)b", Flags::default()).parse(),
+            Err(ParseError::new(ErrorKind::UnopenedGroup, 1..2))
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |open: &str, depth| format!("{}a{}", open.repeat(depth), ")".repeat(depth));
+        assert_eq!(
+            parse(&nested("(", MAX_NESTING)).backrefs.unwrap().len(),
+            250
+        );
+        assert_eq!(
+            Parser::new(&nested("(?:", 10_000), Flags::default()).parse(),
+            Err(ParseError::new(ErrorKind::NestingTooDeep, 750..751))
+        );
+        assert_eq!(
+            Parser::new(&nested("(?=", 251), Flags::default()).parse(),
+            Err(ParseError::new(ErrorKind::NestingTooDeep, 750..751))
+        );
+    }
+
+    #[test]
+    fn bracket_expression() {
+        let re = parse("[^]a-c\\d[:alpha:]-]");
+        assert_eq!(
+            re.rtype,
+            vec![RType::Ccl(CharClass {
+                items: vec![
+                    ClassItem::Ch(']'),
+                    ClassItem::Range('a', 'c'),
+                    ClassItem::Digit,
+                    ClassItem::Posix(Posix::Alpha),
+                    ClassItem::Ch('-'),
+                ],
+                negated: true,
+            })]
+        );
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(parse(r"\x{1F600}").rtype, vec![RType::Ch('\u{1F600}')]);
+        assert_eq!(
+            parse(r"\u{e9}\u00e9").rtype,
+            vec![RType::Ch('é'), RType::Ch('é')]
+        );
+    }
+
+    #[test]
+    fn flags_change_the_parsed_rtypes() {
+        assert_eq!(
+            parse("(?s:.).").rtype,
+            vec![
+                RType::Group(Box::new(vec![RType::AnyChar])),
+                RType::Wildcard
+            ]
+        );
+        assert_eq!(
+            parse("(?i)k1").rtype,
+            vec![
+                RType::Ccl(CharClass {
+                    items: vec![
+                        ClassItem::Ch('k'),
+                        ClassItem::Ch('K'),
+                        ClassItem::Ch('\u{212A}')
+                    ],
+                    negated: false,
+                }),
+                RType::Ch('1'),
+            ]
+        );
+        assert_eq!(
+            parse("(?x) a  # comment\n b").rtype,
+            vec![RType::Ch('a'), RType::Ch('b')]
+        );
+        assert_eq!(
+            parse("^(?m)^$").rtype,
+            vec![
+                RType::Anchor(Anchor::Start),
+                RType::Anchor(Anchor::LineStart),
+                RType::Anchor(Anchor::LineEnd)
+            ]
+        );
+    }
+
+    #[test]
+    fn lookbehind_lengths() {
+        for (pattern, expected) in [
+            ("(?<=abc)", (3, 3)),
+            ("(?<=a|bc)", (1, 2)),
+            ("(?<=a{2,5}b?)", (2, 6)),
+            ("(?<=(a|é)(?=x)$)", (1, 1)),
+            ("(?<!x?)", (0, 1)),
+        ] {
+            match &parse(pattern).rtype[..] {
+                [RType::LookBehind(min_len, max_len, _)
+                | RType::NegLookBehind(min_len, max_len, _)] => {
+                    assert_eq!((*min_len, *max_len), expected, "{}", pattern)
+                }
+                rtype => panic!("{} parsed as {:?}", pattern, rtype),
+            }
+        }
+        // the longest match has to be known
+        for (pattern, kind, span) in [
+            ("(?<!a+)", ErrorKind::UnboundedLookBehind, 0..7),
+            ("x(?<=(a)\\1)", ErrorKind::UnboundedLookBehind, 1..11),
+            ("(?<=a*", ErrorKind::UnclosedGroup, 0..6),
+        ] {
+            assert_eq!(
+                Parser::new(pattern, Flags::default()).parse(),
+                Err(ParseError::new(kind, span)),
+                "{}",
+                pattern
+            );
+        }
+        assert_eq!(
+            parse("a(?!b)").rtype,
+            vec![
+                RType::Ch('a'),
+                RType::NegLookAhead(Box::new(vec![RType::Ch('b')]))
+            ]
+        );
+        assert_eq!(
+            Parser::new("(?=a", Flags::default()).parse(),
+            Err(ParseError::new(ErrorKind::UnclosedGroup, 0..4))
+        );
+    }
+
+    #[test]
+    fn non_capturing_groups() {
+        let re = parse("(?:a|b)(c)(?:d(e))+");
+        assert_eq!(
+            re.rtype,
+            vec![
+                RType::Group(Box::new(vec![RType::AltOr(vec![
+                    vec![RType::Ch('a')],
+                    vec![RType::Ch('b')]
+                ])])),
+                RType::Capture(1, Box::new(vec![RType::Ch('c')])),
+                RType::Repeat(
+                    Box::new(RType::Group(Box::new(vec![
+                        RType::Ch('d'),
+                        RType::Capture(2, Box::new(vec![RType::Ch('e')]))
+                    ]))),
+                    Quantifier::PLUS
+                ),
+            ]
+        );
+        assert_eq!(re.backrefs, Some(vec![(7..10, None), (14..17, None)]));
+        assert_eq!(parse("(?:)").rtype, vec![RType::Group(Box::default())]);
+    }
+}
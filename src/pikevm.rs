@@ -0,0 +1,299 @@
+use crate::backtrack::Slots;
+use crate::char_at;
+use crate::nfa::{Inst, Program, SparseSet};
+
+/// Finds the leftmost match of `program` in `haystack`, starting the search at byte offset
+/// `start`. Every thread of the NFA runs in lockstep over the haystack, so the search takes
+/// O(program × haystack) time whatever the pattern.
+pub(crate) fn search(program: &Program, haystack: &str, start: usize) -> Option<Slots> {
+    search_with(program, &mut Cache::new(program), haystack, start)
+}
+
+/// Thread lists of the Pike VM for a program, kept from one search to the next so that they're
+/// only allocated once. They start empty and grow with the threads the searches run, which are
+/// usually far fewer than the instructions of the program.
+#[derive(Debug, Clone)]
+pub(crate) struct Cache {
+    clist: Threads,
+    nlist: Threads,
+    stack: Vec<Frame>,
+    scratch: Vec<Option<usize>>,
+}
+
+impl Cache {
+    pub(crate) fn new(program: &Program) -> Self {
+        Cache {
+            clist: Threads::new(program.slots),
+            nlist: Threads::new(program.slots),
+            stack: vec![],
+            scratch: vec![None; program.slots],
+        }
+    }
+}
+
+/// Same as [`search`], reusing the thread lists of `cache`, which was created for `program`.
+pub(crate) fn search_with(
+    program: &Program,
+    cache: &mut Cache,
+    haystack: &str,
+    start: usize,
+) -> Option<Slots> {
+    let Cache {
+        clist,
+        nlist,
+        stack,
+        scratch,
+    } = cache;
+    clist.set.clear();
+    nlist.set.clear();
+    let mut pikevm = PikeVM {
+        program,
+        haystack,
+        stack,
+    };
+    let mut matched = None;
+    let mut at = start;
+    loop {
+        if matched.is_none() && (!program.anchored || at == start) {
+            // a thread starting here has a lower priority than every thread started earlier
+            scratch.fill(None);
+            pikevm.add_thread(clist, 0, at, scratch);
+        }
+        if clist.set.is_empty() && (matched.is_some() || program.anchored) {
+            break;
+        }
+        let c = char_at(haystack, at);
+        for &pc in clist.set.iter() {
+            match &program.insts[pc] {
+                Inst::Char(rtype) => {
+                    if let Some(c) = c.filter(|&c| rtype.matches_char(c)) {
+                        scratch.copy_from_slice(clist.slots(pc));
+                        pikevm.add_thread(nlist, pc + 1, at + c.len_utf8(), scratch);
+                    }
+                }
+                Inst::Match => {
+                    // the threads after this one have a lower priority, they can't do better
+                    matched = Some(clist.slots(pc).to_vec());
+                    break;
+                }
+                // the other instructions were already followed when the thread was added
+                _ => {}
+            }
+        }
+        std::mem::swap(clist, nlist);
+        nlist.set.clear();
+        match c {
+            Some(c) => at += c.len_utf8(),
+            None => break,
+        }
+    }
+    matched.map(|slots| {
+        slots
+            .chunks(2)
+            .map(|span| match span {
+                [Some(start), Some(end)] => Some(*start..*end),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Frame {
+    Explore(usize),                    // follow the instruction and the ones it leads to
+    RestoreSlot(usize, Option<usize>), // undo a Save once everything after it was explored
+}
+
+struct PikeVM<'a> {
+    program: &'a Program,
+    haystack: &'a str,
+    stack: &'a mut Vec<Frame>,
+}
+
+impl PikeVM<'_> {
+    // Adds a thread at `pc` with the capture `slots` to `list`, following every instruction that
+    // doesn't consume a character so that only Char and Match instructions end up in the list
+    fn add_thread(
+        &mut self,
+        list: &mut Threads,
+        pc: usize,
+        at: usize,
+        slots: &mut [Option<usize>],
+    ) {
+        self.stack.push(Frame::Explore(pc));
+        while let Some(frame) = self.stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::RestoreSlot(slot, position) => {
+                    slots[slot] = position;
+                    continue;
+                }
+            };
+            // a thread that already reached this instruction had a higher priority
+            if !list.set.insert(pc) {
+                continue;
+            }
+            match &self.program.insts[pc] {
+                Inst::Jmp(next) => self.stack.push(Frame::Explore(*next)),
+                Inst::Split(first, second) => {
+                    self.stack.push(Frame::Explore(*second));
+                    self.stack.push(Frame::Explore(*first));
+                }
+                Inst::Save(slot) => {
+                    self.stack.push(Frame::RestoreSlot(*slot, slots[*slot]));
+                    slots[*slot] = Some(at);
+                    self.stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Assert(anchor) => {
+                    if anchor.matches_at(self.haystack, at) {
+                        self.stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                Inst::Char(_) | Inst::Match => list.slots_mut(pc).copy_from_slice(slots),
+            }
+        }
+    }
+}
+
+// Threads ordered by priority, one per instruction at most, along with their capture slots. The
+// slots of a thread are stored in the order it was added.
+#[derive(Debug, Clone)]
+struct Threads {
+    set: SparseSet,
+    slots: Vec<Option<usize>>,
+    stride: usize,
+}
+
+impl Threads {
+    fn new(stride: usize) -> Self {
+        Threads {
+            set: SparseSet::default(),
+            slots: vec![],
+            stride,
+        }
+    }
+
+    fn slots(&self, pc: usize) -> &[Option<usize>] {
+        let start = self.set.index(pc).expect("thread is in the list") * self.stride;
+        &self.slots[start..start + self.stride]
+    }
+
+    fn slots_mut(&mut self, pc: usize) -> &mut [Option<usize>] {
+        let start = self.set.index(pc).expect("thread is in the list") * self.stride;
+        if self.slots.len() < start + self.stride {
+            self.slots.resize(start + self.stride, None);
+        }
+        &mut self.slots[start..start + self.stride]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backtrack, get_regex_pattern};
+
+    fn find(pattern: &str, haystack: &str) -> Option<Slots> {
+        let program = Program::compile(&get_regex_pattern(pattern).unwrap()).unwrap();
+        search(&program, haystack, 0)
+    }
+
+    #[test]
+    fn agrees_with_backtracker() {
+        let cases = [
+            ("a+ab", "aaab"),
+            (".*foo.*bar", "foo bar foo"),
+            ("a{2,4}a{3}", "aaaa"),
+            ("x*y*z*", ""),
+            ("(a|ab)(c|bcd)(d*)", "abcd"),
+            ("(ab|a)+b", "ab"),
+            ("<(.+?)>", "<a><b>"),
+            ("(a+?)(a+)", "aaaa"),
+            ("(x)?y", "zy"),
+            ("((a)|(b))+", "cab"),
+            ("(a*)*", "b"),
+            ("(a*)+b", "aab"),
+            ("(|a)+", "aa"),
+            ("^(a|b)c", "bc"),
+            ("^b", "ab"),
+            ("(a|b)c$", "acbc"),
+            ("(?m)^b$", "a\nb\nc"),
+            ("(^a|b$)+", "aab"),
+            ("a\\Z", "a\n"),
+            ("é+", "caféé!"),
+            ("[^a]", "aaa"),
+            // an optional iteration matching the empty string is taken, then the repetition stops
+            ("(x?)?", ""),
+            ("(^|b)?c?", "bc"),
+            ("(a?){0,3}b", "b"),
+            ("(a?)*", ""),
+            ("(a?){2}", "b"),
+            ("(|a){1,2}a", "aa"),
+            ("(a|)+b", "ab"),
+            ("a{0,2}(?:([ab]*|...|)*)", "ccc"),
+        ];
+        for (pattern, haystack) in cases {
+            let re = get_regex_pattern(pattern).unwrap();
+            let expected = (0..=haystack.len())
+                .filter(|&at| haystack.is_char_boundary(at))
+                .find_map(|at| backtrack::match_at(&re, haystack, at).unwrap());
+            assert_eq!(
+                find(pattern, haystack),
+                expected,
+                "{} on {:?}",
+                pattern,
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn search_from_an_offset() {
+        let program = Program::compile(&get_regex_pattern("a(b)").unwrap()).unwrap();
+        assert_eq!(
+            search(&program, "abab", 1),
+            Some(vec![Some(2..4), Some(3..4)])
+        );
+        assert_eq!(search(&program, "abab", 3), None);
+    }
+
+    #[test]
+    fn cache_is_reused_across_searches() {
+        let program = Program::compile(&get_regex_pattern("(a+)(b)?").unwrap()).unwrap();
+        let mut cache = Cache::new(&program);
+        for (haystack, expected) in [
+            ("xaab", Some(vec![Some(1..4), Some(1..3), Some(3..4)])),
+            ("b", None),
+            ("a", Some(vec![Some(0..1), Some(0..1), None])),
+        ] {
+            assert_eq!(
+                search_with(&program, &mut cache, haystack, 0),
+                expected,
+                "{:?}",
+                haystack
+            );
+        }
+    }
+
+    #[test]
+    fn thread_lists_grow_with_the_threads() {
+        let program = Program::compile(&get_regex_pattern("(x)(?:a{1000}){60}").unwrap()).unwrap();
+        let mut cache = Cache::new(&program);
+        assert!(cache.clist.slots.is_empty() && cache.nlist.slots.is_empty());
+        assert_eq!(search_with(&program, &mut cache, "xaab", 0), None);
+        // a handful of threads ran, not the 60000 instructions
+        let len = cache.clist.slots.len().max(cache.nlist.slots.len());
+        assert!(len <= 10 * program.slots, "{}", len);
+    }
+
+    #[test]
+    fn adversarial_patterns_run_in_linear_time() {
+        // exponential for a backtracker
+        let haystack = "a".repeat(10_000);
+        assert_eq!(find("(a|a)*b", &haystack), None);
+        assert_eq!(find("(a*)*b", &haystack), None);
+        assert_eq!(
+            find("(a|aa)*$", &haystack).map(|slots| slots[0].clone()),
+            Some(Some(0..10_000))
+        );
+    }
+}
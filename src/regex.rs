@@ -0,0 +1,625 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::backtrack::{self, Slots};
+use crate::nfa::Program;
+use crate::{char_at, dfa, pikevm, Flags, LazyDfa, MatchError, ParseError, RE};
+
+/// Compiled pattern which, unlike [`RE`], is never changed by a search: what a search needs to
+/// keep track of lives in a separate [`Cache`]. A `Regex` is `Send + Sync`, so it can be shared
+/// between threads behind an `Arc`, every thread searching with a cache of its own.
+///
+/// The methods without a `_with` suffix, like [`Regex::is_match`], create a cache for a single
+/// search and drop it, throwing away the states their lazy DFA computed. To search many
+/// haystacks, like the lines of a file, create a cache once with [`Regex::create_cache`] and pass
+/// it to [`Regex::is_match_with`], [`Regex::find_with`] or [`Regex::captures_with`].
+///
+/// Searches return a [`MatchError`] when a pattern which needs backtracking, because of its
+/// lookarounds, atomic groups or back-references, takes too long to match a haystack. Other
+/// patterns always run in time linear in the haystack and never fail.
+#[derive(Debug)]
+pub struct Regex {
+    re: RE,
+    program: Option<Program>, // `None` when the pattern needs the backtracker
+    group_names: Arc<[Option<String>]>,
+}
+
+/// Mutable state of the searches of a [`Regex`], like the states of its lazy DFA, which is reused
+/// from one search to the next. A cache must only be used with the regex which created it.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dfa: Option<dfa::Cache>,
+    pikevm: Option<pikevm::Cache>,
+}
+
+/// Part of a haystack matched by a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'h> {
+    haystack: &'h str,
+    start: usize,
+    end: usize,
+}
+
+/// Iterator over the matches of a [`Regex`] in a haystack, see [`Regex::find_iter`].
+#[derive(Debug)]
+pub struct Matches<'r, 'h>(Searches<'r, 'h>);
+
+/// Iterator over the captures of every match of a [`Regex`] in a haystack, see
+/// [`Regex::captures_iter`].
+#[derive(Debug)]
+pub struct CaptureMatches<'r, 'h>(Searches<'r, 'h>);
+
+// Successive searches of a regex in a haystack, each one starting where the previous match ended
+#[derive(Debug)]
+struct Searches<'r, 'h> {
+    regex: &'r Regex,
+    cache: Cache,
+    haystack: &'h str,
+    at: usize,               // where the next search starts
+    last_end: Option<usize>, // where the previous match ended
+    failed: bool,            // whether a search gave up, which ends the iteration
+}
+
+/// Groups captured by a match, group 0 being the whole match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures<'h> {
+    haystack: &'h str,
+    slots: Slots,
+    group_names: Arc<[Option<String>]>,
+}
+
+impl Regex {
+    /// Compiles `pattern`, returning a [`ParseError`] pointing at the offending part of the
+    /// pattern if it's invalid.
+    pub fn new(pattern: &str) -> Result<Regex, ParseError> {
+        Ok(Regex::from(RE::new(pattern)?))
+    }
+
+    /// Same as [`Regex::new`], with `flags` instead of the default ones.
+    pub fn with_flags(pattern: &str, flags: Flags) -> Result<Regex, ParseError> {
+        Ok(Regex::from(RE::with_flags(pattern, flags)?))
+    }
+
+    /// The parsed pattern.
+    pub fn as_re(&self) -> &RE {
+        &self.re
+    }
+
+    /// Number of the capture group called `name`, see [`RE::group_index`].
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.re.group_index(name)
+    }
+
+    /// Name of capture group `index`, if it has one.
+    pub fn group_name(&self, index: usize) -> Option<&str> {
+        self.re.group_name(index)
+    }
+
+    /// Creates a cache to search with this regex. It's empty at first, what it holds grows with
+    /// what the searches need.
+    pub fn create_cache(&self) -> Cache {
+        self.create_cache_with_capacity(LazyDfa::DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Same as [`Regex::create_cache`], the states of the lazy DFA taking up to
+    /// `dfa_cache_capacity` bytes before they're flushed, see [`LazyDfa`].
+    pub fn create_cache_with_capacity(&self, dfa_cache_capacity: usize) -> Cache {
+        Cache::new(self.program.as_ref(), dfa_cache_capacity)
+    }
+
+    /// Whether the pattern matches somewhere in `haystack`. Creates a cache for this search only,
+    /// use [`Regex::is_match_with`] when searching more than one haystack.
+    pub fn is_match(&self, haystack: &str) -> Result<bool, MatchError> {
+        self.is_match_with(&mut self.create_cache(), haystack)
+    }
+
+    /// Same as [`Regex::is_match`], with a `cache` created by this regex which keeps the states
+    /// of its lazy DFA from one search to the next.
+    pub fn is_match_with(&self, cache: &mut Cache, haystack: &str) -> Result<bool, MatchError> {
+        if let (Some(program), Some(dfa)) = (&self.program, cache.dfa.as_mut()) {
+            if let Some(is_match) = dfa.is_match(program, haystack) {
+                return Ok(is_match);
+            }
+        }
+        // the DFA gave up, running it again over the haystack wouldn't help
+        Ok(search_nfa(&self.re, self.program.as_ref(), cache, haystack, 0)?.is_some())
+    }
+
+    /// Groups captured by the leftmost match of the pattern in `haystack`. Creates a cache for
+    /// this search only, use [`Regex::captures_with`] when searching more than one haystack.
+    pub fn captures<'h>(&self, haystack: &'h str) -> Result<Option<Captures<'h>>, MatchError> {
+        self.captures_with(&mut self.create_cache(), haystack)
+    }
+
+    /// Same as [`Regex::captures`], with a `cache` created by this regex which is reused
+    /// between searches.
+    pub fn captures_with<'h>(
+        &self,
+        cache: &mut Cache,
+        haystack: &'h str,
+    ) -> Result<Option<Captures<'h>>, MatchError> {
+        let slots = self.search(cache, haystack)?;
+        Ok(slots.map(|slots| Captures {
+            haystack,
+            slots,
+            group_names: Arc::clone(&self.group_names),
+        }))
+    }
+
+    /// Leftmost match of the pattern in `haystack`. Like with a backtracking engine, the
+    /// alternatives of the pattern are tried in order: `a|ab` only matches the `a` of `ab`.
+    /// Creates a cache for this search only, use [`Regex::find_with`] when searching more than one
+    /// haystack.
+    pub fn find<'h>(&self, haystack: &'h str) -> Result<Option<Match<'h>>, MatchError> {
+        self.find_with(&mut self.create_cache(), haystack)
+    }
+
+    /// Same as [`Regex::find`], with a `cache` created by this regex which is reused between
+    /// searches.
+    pub fn find_with<'h>(
+        &self,
+        cache: &mut Cache,
+        haystack: &'h str,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        let slots = self.search(cache, haystack)?;
+        Ok(slots.map(|slots| Match::from_slots(haystack, &slots)))
+    }
+
+    /// Iterator over the successive non-overlapping matches of the pattern in `haystack`. An
+    /// empty match right where the previous match ended is skipped, so `a*` matches twice in
+    /// `baa`: the empty string at 0 then `aa`, but not the empty string at the end. The iteration
+    /// stops after a search which gave up.
+    pub fn find_iter<'r, 'h>(&'r self, haystack: &'h str) -> Matches<'r, 'h> {
+        Matches(Searches::new(self, haystack))
+    }
+
+    /// Same as [`Regex::find_iter`], with the groups captured by every match.
+    pub fn captures_iter<'r, 'h>(&'r self, haystack: &'h str) -> CaptureMatches<'r, 'h> {
+        CaptureMatches(Searches::new(self, haystack))
+    }
+
+    fn search(&self, cache: &mut Cache, haystack: &str) -> Result<Option<Slots>, MatchError> {
+        self.search_at(cache, haystack, 0)
+    }
+
+    fn search_at(
+        &self,
+        cache: &mut Cache,
+        haystack: &str,
+        start: usize,
+    ) -> Result<Option<Slots>, MatchError> {
+        search_at(&self.re, self.program.as_ref(), cache, haystack, start)
+    }
+}
+
+/// Capture slots of the leftmost match of `re` starting at byte offset `start` or after it. What's
+/// before `start` is still looked at by anchors and lookbehinds. `program` is the one compiled
+/// from `re`, and `cache` was created for it.
+pub(crate) fn search_at(
+    re: &RE,
+    program: Option<&Program>,
+    cache: &mut Cache,
+    haystack: &str,
+    start: usize,
+) -> Result<Option<Slots>, MatchError> {
+    // most haystacks don't match when grepping, the DFA rejects them without tracking captures
+    if let (0, Some(program), Some(dfa)) = (start, program, cache.dfa.as_mut()) {
+        if dfa.is_match(program, haystack) == Some(false) {
+            return Ok(None);
+        }
+    }
+    search_nfa(re, program, cache, haystack, start)
+}
+
+// Same as `search_at` without rejecting the haystack with the DFA first
+fn search_nfa(
+    re: &RE,
+    program: Option<&Program>,
+    cache: &mut Cache,
+    haystack: &str,
+    start: usize,
+) -> Result<Option<Slots>, MatchError> {
+    match (program, cache.pikevm.as_mut()) {
+        (Some(program), Some(pikevm)) => Ok(pikevm::search_with(program, pikevm, haystack, start)),
+        _ => backtrack::search(re, haystack, start, re.is_anchored_start()),
+    }
+}
+
+impl Cache {
+    // Cache of the engines which can run `program`, without any for the backtracker
+    pub(crate) fn new(program: Option<&Program>, dfa_cache_capacity: usize) -> Self {
+        let Some(program) = program else {
+            return Cache {
+                dfa: None,
+                pikevm: None,
+            };
+        };
+        Cache {
+            dfa: dfa::is_supported(program).then(|| dfa::Cache::new(program, dfa_cache_capacity)),
+            pikevm: Some(pikevm::Cache::new(program)),
+        }
+    }
+}
+
+impl From<RE> for Regex {
+    fn from(re: RE) -> Self {
+        let program = Program::compile(&re);
+        let group_names = re.group_names.clone().into();
+        Regex {
+            re,
+            program,
+            group_names,
+        }
+    }
+}
+
+impl<'r, 'h> Searches<'r, 'h> {
+    fn new(regex: &'r Regex, haystack: &'h str) -> Self {
+        Searches {
+            regex,
+            cache: regex.create_cache(),
+            haystack,
+            at: 0,
+            last_end: None,
+            failed: false,
+        }
+    }
+}
+
+impl Iterator for Searches<'_, '_> {
+    type Item = Result<Slots, MatchError>;
+
+    fn next(&mut self) -> Option<Result<Slots, MatchError>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            let slots = match self
+                .regex
+                .search_at(&mut self.cache, self.haystack, self.at)
+            {
+                Ok(slots) => slots?,
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+            };
+            let span = slots[0].clone().expect("group 0 is the whole match");
+            if span.is_empty() && Some(span.end) == self.last_end {
+                // the next search has to make progress, without splitting a character
+                self.at += char_at(self.haystack, self.at)?.len_utf8();
+                continue;
+            }
+            self.at = span.end;
+            self.last_end = Some(span.end);
+            return Some(Ok(slots));
+        }
+    }
+}
+
+impl<'h> Iterator for Matches<'_, 'h> {
+    type Item = Result<Match<'h>, MatchError>;
+
+    fn next(&mut self) -> Option<Result<Match<'h>, MatchError>> {
+        let slots = self.0.next()?;
+        Some(slots.map(|slots| Match::from_slots(self.0.haystack, &slots)))
+    }
+}
+
+impl<'h> Iterator for CaptureMatches<'_, 'h> {
+    type Item = Result<Captures<'h>, MatchError>;
+
+    fn next(&mut self) -> Option<Result<Captures<'h>, MatchError>> {
+        let slots = self.0.next()?;
+        Some(slots.map(|slots| Captures {
+            haystack: self.0.haystack,
+            slots,
+            group_names: Arc::clone(&self.0.regex.group_names),
+        }))
+    }
+}
+
+impl<'h> Match<'h> {
+    fn from_slots(haystack: &'h str, slots: &Slots) -> Self {
+        let span = slots[0].clone().expect("group 0 is the whole match");
+        Match {
+            haystack,
+            start: span.start,
+            end: span.end,
+        }
+    }
+
+    /// Byte offset of the start of the match in the haystack.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset right after the end of the match in the haystack.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The matched part of the haystack.
+    pub fn as_str(&self) -> &'h str {
+        &self.haystack[self.range()]
+    }
+}
+
+impl<'h> Captures<'h> {
+    /// Text captured by group `index`, `None` if the group didn't take part in the match.
+    pub fn get(&self, index: usize) -> Option<&'h str> {
+        self.span(index).map(|span| &self.haystack[span])
+    }
+
+    /// Byte range of the haystack captured by group `index`.
+    pub fn span(&self, index: usize) -> Option<Range<usize>> {
+        self.slots.get(index)?.clone()
+    }
+
+    /// Text captured by the group called `name`.
+    pub fn name(&self, name: &str) -> Option<&'h str> {
+        let slot = self
+            .group_names
+            .iter()
+            .position(|other| other.as_deref() == Some(name))?;
+        self.get(slot + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn captures() {
+        let regex = Regex::new(r"(?<key>\w+)=(\w+)?(,)?").unwrap();
+        let captures = regex.captures("x, name=, b=2").unwrap().unwrap();
+        assert_eq!(captures.get(0), Some("name=,"));
+        assert_eq!(captures.span(0), Some(3..9));
+        assert_eq!(captures.name("key"), Some("name"));
+        assert_eq!(captures.get(2), None);
+        assert_eq!(captures.get(3), Some(","));
+        assert_eq!(captures.get(4), None);
+        assert_eq!(captures.name("value"), None);
+        assert_eq!(regex.captures("no pairs").unwrap(), None);
+    }
+
+    #[test]
+    fn find() {
+        for (pattern, haystack, expected) in [
+            ("a|ab", "xab", Some((1, 2, "a"))),
+            ("ab|a", "xab", Some((1, 3, "ab"))),
+            ("b|ab", "xab", Some((1, 3, "ab"))),
+            ("a+?", "baaa", Some((1, 2, "a"))),
+            (r"\d+", "café 42", Some((6, 8, "42"))),
+            ("é+", "cafééé!", Some((3, 9, "ééé"))),
+            ("x*", "abc", Some((0, 0, ""))),
+            ("$", "abc", Some((3, 3, ""))),
+            (r"(\w)\1", "abccd", Some((2, 4, "cc"))),
+            (r"(?<=\$)\d+", "5 or $42", Some((6, 8, "42"))),
+            (r"\bfoo\b", "foobar", None),
+        ] {
+            let found = Regex::new(pattern).unwrap().find(haystack).unwrap();
+            assert_eq!(
+                found.map(|m| (m.start(), m.end(), m.as_str())),
+                expected,
+                "{} on {:?}",
+                pattern,
+                haystack
+            );
+        }
+        let m = Regex::new("b+").unwrap().find("abbc").unwrap().unwrap();
+        assert_eq!(m.range(), 1..3);
+        assert!(!m.is_empty());
+    }
+
+    #[test]
+    fn find_iter() {
+        let matches = |pattern: &str, haystack| -> Vec<_> {
+            Regex::new(pattern)
+                .unwrap()
+                .find_iter(haystack)
+                .map(|m| m.map(|m| (m.start(), m.as_str())))
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(
+            matches(r"\d+", "1 22 333"),
+            [(0, "1"), (2, "22"), (5, "333")]
+        );
+        assert_eq!(matches("aa", "aaaaa"), [(0, "aa"), (2, "aa")]);
+        assert_eq!(matches("x", "abc"), []);
+        // empty matches
+        assert_eq!(matches("a*", "baa"), [(0, ""), (1, "aa")]);
+        assert_eq!(matches("a*", "bab"), [(0, ""), (1, "a"), (3, "")]);
+        assert_eq!(matches("", "ab"), [(0, ""), (1, ""), (2, "")]);
+        assert_eq!(matches("", ""), [(0, "")]);
+        // progress is made a character at a time, not a byte
+        assert_eq!(matches("", "é💩"), [(0, ""), (2, ""), (6, "")]);
+        assert_eq!(matches("x*", "éx"), [(0, ""), (2, "x")]);
+        // anchors and lookbehinds still see the whole haystack
+        assert_eq!(matches("^a", "aaa"), [(0, "a")]);
+        assert_eq!(matches(r"\ba", "aa a"), [(0, "a"), (3, "a")]);
+        assert_eq!(matches(r"(?<=a)b", "abab"), [(1, "b"), (3, "b")]);
+        assert_eq!(matches(r"(?m)^\w", "ab\ncd"), [(0, "a"), (3, "c")]);
+        // on the backtracker
+        assert_eq!(matches(r"(\w)\1", "aabbcd"), [(0, "aa"), (2, "bb")]);
+        assert_eq!(matches(r"(?>a*)", "baa"), [(0, ""), (1, "aa")]);
+    }
+
+    #[test]
+    fn captures_iter() {
+        let regex = Regex::new(r"(?<key>\w+)=(?<value>\w*)").unwrap();
+        let pairs: Vec<_> = regex
+            .captures_iter("a=1, b=, long=value")
+            .map(|captures| captures.map(|c| (c.name("key"), c.name("value"))))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            pairs,
+            [
+                (Some("a"), Some("1")),
+                (Some("b"), Some("")),
+                (Some("long"), Some("value"))
+            ]
+        );
+        let regex = Regex::new(r"(a)?b").unwrap();
+        let groups: Vec<_> = regex
+            .captures_iter("abb")
+            .map(|captures| captures.map(|c| c.get(1)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(groups, [Some("a"), None]);
+    }
+
+    #[test]
+    fn searches_give_up_on_exponential_backtracking() {
+        let regex = Regex::new(r"(a*)*\1b").unwrap();
+        let haystack = "a".repeat(25);
+        assert_eq!(regex.is_match(&haystack), Err(MatchError::BacktrackLimit));
+        assert_eq!(regex.find(&haystack), Err(MatchError::BacktrackLimit));
+        // the iteration stops after the error
+        let mut matches = regex.find_iter(&haystack);
+        assert_eq!(matches.next(), Some(Err(MatchError::BacktrackLimit)));
+        assert_eq!(matches.next(), None);
+        assert_eq!(regex.is_match("aab"), Ok(true));
+    }
+
+    #[test]
+    fn every_engine_gives_the_same_captures() {
+        for pattern in [r"(a+)(b)?c", r"(?>(a+))(b)?c", r"(a+)(b)?(?=c)c"] {
+            let regex = Regex::new(pattern).unwrap();
+            let captures = regex.captures("xaaac").unwrap().unwrap();
+            assert_eq!(captures.slots, vec![Some(1..5), Some(1..4), None]);
+        }
+        // an empty lookahead sends the pattern to the backtracker
+        for (pattern, haystack, expected) in [
+            // mandatory iterations all run, even after one matched the empty string
+            ("(x*?){2}y", "xxy", vec![Some(0..3), Some(0..2)]),
+            ("(|a){2}b", "ab", vec![Some(0..2), Some(0..1)]),
+            ("(?:^c?){2}", "c", vec![Some(0..1)]),
+            ("(a?){2}", "b", vec![Some(0..0), Some(0..0)]),
+            ("(a|b){2,3}?c", "abbc", vec![Some(0..4), Some(2..3)]),
+            // an optional iteration matching the empty string ends the repetition, the rest of
+            // the pattern goes on from there
+            ("(a?)*", "", vec![Some(0..0), Some(0..0)]),
+            ("(a|)+b", "ab", vec![Some(0..2), Some(1..1)]),
+            ("(a*)+$", "ba", vec![Some(1..2), Some(2..2)]),
+            (
+                "a{0,2}(?:([ab]*|...|)*)",
+                "ccc",
+                vec![Some(0..0), Some(0..0)],
+            ),
+        ] {
+            for pattern in [pattern.to_string(), format!("(?=){}", pattern)] {
+                let captures = Regex::new(&pattern)
+                    .unwrap()
+                    .captures(haystack)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(captures.slots, expected, "{} on {:?}", pattern, haystack);
+            }
+        }
+    }
+
+    #[test]
+    fn captures_dont_leak_between_searches() {
+        let regex = Regex::new(r"(a)?b").unwrap();
+        let mut cache = regex.create_cache();
+        assert_eq!(
+            regex
+                .captures_with(&mut cache, "ab")
+                .unwrap()
+                .unwrap()
+                .get(1),
+            Some("a")
+        );
+        assert_eq!(
+            regex
+                .captures_with(&mut cache, "b")
+                .unwrap()
+                .unwrap()
+                .get(1),
+            None
+        );
+        // nor between start positions
+        let regex = Regex::new(r"(?:(a)z)?b").unwrap();
+        let captures = regex.captures("axb").unwrap().unwrap();
+        assert_eq!((captures.get(0), captures.get(1)), (Some("b"), None));
+    }
+
+    #[test]
+    fn cache_is_reused_across_searches() {
+        // on the DFA, the backtracker and the Pike VM
+        for pattern in [r"\bfoo\d", r"(?>fo+)\d", r"foo\d\Z"] {
+            let regex = Regex::new(pattern).unwrap();
+            let mut cache = regex.create_cache();
+            for (haystack, expected) in [("a foo1", true), ("foo", false), ("", false)] {
+                assert_eq!(
+                    regex.is_match_with(&mut cache, haystack).unwrap(),
+                    expected,
+                    "{} on {:?}",
+                    pattern,
+                    haystack
+                );
+            }
+        }
+        // the legacy API doesn't match empty haystacks, this one does
+        assert!(Regex::new("^$").unwrap().is_match("").unwrap());
+    }
+
+    #[test]
+    fn small_dfa_cache() {
+        // the DFA keeps being flushed and gives up, the Pike VM answers instead
+        let regex = Regex::new("a.{12}$").unwrap();
+        let mut cache = regex.create_cache_with_capacity(2048);
+        let haystack = "ab".repeat(100);
+        assert!(!regex.is_match_with(&mut cache, &haystack).unwrap());
+        let haystack = haystack + "b";
+        let (program, dfa) = (regex.program.as_ref().unwrap(), cache.dfa.as_mut().unwrap());
+        assert_eq!(dfa.is_match(program, &haystack), None);
+        assert!(regex.is_match_with(&mut cache, &haystack).unwrap());
+        assert_eq!(
+            regex
+                .find_with(&mut cache, &haystack)
+                .unwrap()
+                .map(|m| m.range()),
+            Some(188..201)
+        );
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Regex>();
+
+        let regex = Arc::new(Regex::new(r"(\d+)-(\d+)").unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let regex = Arc::clone(&regex);
+                thread::spawn(move || {
+                    let mut cache = regex.create_cache();
+                    (0..100).all(|j| {
+                        let haystack = format!("{}-{}", i, j);
+                        let captures = regex.captures_with(&mut cache, &haystack).unwrap().unwrap();
+                        captures.get(1) == Some(&i.to_string()[..])
+                            && captures.get(2) == Some(&j.to_string()[..])
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+}
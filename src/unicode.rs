@@ -0,0 +1,187 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::slice;
+
+#[rustfmt::skip]
+mod tables;
+
+use tables::Ranges;
+
+/// A Unicode property as used in `\p{..}`, either a general category like `L` or `Nd`, or a
+/// script like `Greek`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Property {
+    name: &'static str,
+    tables: &'static [Ranges],
+}
+
+impl Property {
+    /// Characters matched by `\d` in Unicode mode.
+    pub const PERL_DIGIT: Property = Property {
+        name: "perldigit",
+        tables: &[tables::PERL_DIGIT],
+    };
+    /// Characters matched by `\s` in Unicode mode.
+    pub const PERL_SPACE: Property = Property {
+        name: "perlspace",
+        tables: &[tables::PERL_SPACE],
+    };
+    /// Characters matched by `\w` in Unicode mode.
+    pub const PERL_WORD: Property = Property {
+        name: "perlword",
+        tables: &[tables::PERL_WORD],
+    };
+
+    /// Looks up a property by its name or alias, like `L`, `Letter`, `Greek` or `Grek`, ignoring
+    /// case, spaces, underscores and hyphens. The name can be prefixed by `gc=` or `sc=` (or
+    /// `General_Category=` and `Script=`) to only look up general categories or scripts, without
+    /// a prefix general categories are looked up first.
+    pub fn from_name(name: &str) -> Option<Property> {
+        let (kind, value) = match name.split_once('=') {
+            Some((kind, value)) => (Some(normalize(kind)), normalize(value)),
+            None => (None, normalize(name)),
+        };
+        let category = || {
+            let i = tables::GENERAL_CATEGORY
+                .binary_search_by_key(&value.as_str(), |(name, _)| name)
+                .ok()?;
+            let (name, tables) = &tables::GENERAL_CATEGORY[i];
+            Some(Property { name, tables })
+        };
+        let script = || {
+            let i = tables::SCRIPT
+                .binary_search_by_key(&value.as_str(), |(name, _)| name)
+                .ok()?;
+            let (name, ranges) = &tables::SCRIPT[i];
+            Some(Property {
+                name,
+                tables: slice::from_ref(ranges),
+            })
+        };
+        match kind.as_deref() {
+            None => category().or_else(script),
+            Some("gc" | "generalcategory") => category(),
+            Some("sc" | "script") => script(),
+            Some(_) => None,
+        }
+    }
+
+    pub fn matches(&self, c: char) -> bool {
+        self.tables.iter().any(|ranges| {
+            ranges
+                .binary_search_by(|&(start, end)| {
+                    if end < c {
+                        Ordering::Less
+                    } else if start > c {
+                        Ordering::Greater
+                    } else {
+                        Ordering::Equal
+                    }
+                })
+                .is_ok()
+        })
+    }
+}
+
+/// Other cases of `c` under simple case folding, like `K` and the Kelvin sign (U+212A) for `k`.
+pub(crate) fn other_cases(c: char) -> &'static [char] {
+    match tables::CASE_FOLDING.binary_search_by_key(&c, |(c, _)| *c) {
+        Ok(i) => tables::CASE_FOLDING[i].1,
+        Err(_) => &[],
+    }
+}
+
+/// Characters of `range` having other cases, along with these other cases.
+pub(crate) fn other_cases_in(
+    range: RangeInclusive<char>,
+) -> impl Iterator<Item = &'static (char, &'static [char])> {
+    let start = tables::CASE_FOLDING.partition_point(|(c, _)| c < range.start());
+    tables::CASE_FOLDING[start..]
+        .iter()
+        .take_while(move |(c, _)| c <= range.end())
+}
+
+// The tables would make for thousands of lines
+impl fmt::Debug for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Property").field(&self.name).finish()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn property_names() {
+        for (name, expected) in [
+            ("L", Some("l")),
+            ("letter", Some("letter")),
+            ("Uppercase_Letter", Some("uppercaseletter")),
+            ("gc=Nd", Some("nd")),
+            ("General Category = Lu", Some("lu")),
+            ("Greek", Some("greek")),
+            ("sc=grek", Some("grek")),
+            ("Script=Old-Italic", Some("olditalic")),
+            ("sc=L", None),
+            ("gc=Greek", None),
+            ("Klingon", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                Property::from_name(name).map(|property| property.name),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn property_matches() {
+        let matches = |name, c| Property::from_name(name).unwrap().matches(c);
+        assert!(matches("L", 'a'));
+        assert!(matches("L", 'ж'));
+        assert!(matches("L", '語'));
+        assert!(!matches("L", '1'));
+        assert!(matches("Lu", 'É'));
+        assert!(!matches("Lu", 'é'));
+        assert!(matches("Nd", '٣'));
+        assert!(!matches("Nd", 'Ⅻ'));
+        assert!(matches("N", 'Ⅻ'));
+        assert!(matches("Greek", 'λ'));
+        assert!(!matches("Greek", 'l'));
+        assert!(matches("Han", '語'));
+        assert!(matches("Common", '1'));
+        assert!(matches("So", '💩'));
+        assert!(matches("Co", '\u{F0000}'));
+        assert!(matches("Cn", '\u{10FFFF}'));
+        assert!(Property::PERL_WORD.matches('é'));
+        assert!(Property::PERL_WORD.matches('\u{301}'));
+        assert!(!Property::PERL_WORD.matches('-'));
+        assert!(Property::PERL_SPACE.matches('\u{3000}'));
+        assert!(Property::PERL_DIGIT.matches('９'));
+    }
+
+    #[test]
+    fn simple_case_folding() {
+        assert_eq!(other_cases('k'), &['K', '\u{212A}']);
+        assert_eq!(other_cases('\u{212A}'), &['K', 'k']);
+        assert_eq!(other_cases('σ'), &['Σ', 'ς']);
+        assert_eq!(other_cases('ß'), &['ẞ']);
+        // only simple case folding, i isn't the same as the Turkish dotless ı
+        assert_eq!(other_cases('I'), &['i']);
+        assert_eq!(other_cases('ı'), &[]);
+        assert_eq!(other_cases('1'), &[]);
+        let others: Vec<_> = other_cases_in('x'..='{').map(|(c, _)| *c).collect();
+        assert_eq!(others, ['x', 'y', 'z']);
+    }
+}